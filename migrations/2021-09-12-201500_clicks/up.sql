@@ -0,0 +1,10 @@
+CREATE TABLE clicks(
+  id         BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  short      TEXT NOT NULL REFERENCES links(short) ON DELETE CASCADE ON UPDATE CASCADE,
+  clicked_at TIMESTAMPTZ NOT NULL,
+  referrer   TEXT,
+  user_agent TEXT,
+  ip_hash    TEXT
+);
+
+CREATE INDEX clicks_short_clicked_at ON clicks(short, clicked_at);
@@ -0,0 +1,4 @@
+ALTER TABLE links
+  DROP COLUMN active_from,
+  DROP COLUMN expires_at,
+  DROP COLUMN fallback_url;
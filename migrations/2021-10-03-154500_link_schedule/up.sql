@@ -0,0 +1,4 @@
+ALTER TABLE links
+  ADD COLUMN active_from  TIMESTAMPTZ,
+  ADD COLUMN expires_at   TIMESTAMPTZ,
+  ADD COLUMN fallback_url TEXT;
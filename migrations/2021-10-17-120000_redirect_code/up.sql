@@ -0,0 +1,3 @@
+ALTER TABLE links
+  ADD COLUMN redirect_code SMALLINT NOT NULL DEFAULT 308
+    CHECK (redirect_code IN (301, 302, 307, 308));
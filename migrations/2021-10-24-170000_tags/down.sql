@@ -0,0 +1,2 @@
+drop table link_tags;
+drop table tags;
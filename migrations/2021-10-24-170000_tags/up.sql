@@ -0,0 +1,12 @@
+CREATE TABLE tags(
+  id   INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE link_tags(
+  short  TEXT NOT NULL REFERENCES links(short) ON DELETE CASCADE ON UPDATE CASCADE,
+  tag_id INT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+  PRIMARY KEY (short, tag_id)
+);
+
+CREATE INDEX link_tags_tag_id ON link_tags(tag_id);
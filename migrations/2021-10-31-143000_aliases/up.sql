@@ -0,0 +1,7 @@
+CREATE TABLE aliases(
+  alias      TEXT PRIMARY KEY,
+  short      TEXT NOT NULL REFERENCES links(short) ON DELETE CASCADE ON UPDATE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX aliases_short ON aliases(short);
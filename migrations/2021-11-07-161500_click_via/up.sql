@@ -0,0 +1,2 @@
+-- the alias a click came in through, or null when it used the link's own short
+ALTER TABLE clicks ADD COLUMN via TEXT;
@@ -0,0 +1 @@
+ALTER TABLE links ADD COLUMN prefix BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- an scrypt hash like users.pw_hash, or null for links anyone can follow
+ALTER TABLE links ADD COLUMN pw_hash TEXT;
@@ -0,0 +1,2 @@
+-- internal links only redirect for users that are logged in
+ALTER TABLE links ADD COLUMN internal BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- links with a max_clicks stop redirecting once clicks_left reaches zero
+ALTER TABLE links ADD COLUMN max_clicks INT;
+ALTER TABLE links ADD COLUMN clicks_left INT;
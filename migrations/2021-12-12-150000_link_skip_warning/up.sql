@@ -0,0 +1,2 @@
+-- links that skip the warning shown before leaving for an untrusted domain
+ALTER TABLE links ADD COLUMN skip_warning BOOLEAN NOT NULL DEFAULT FALSE;
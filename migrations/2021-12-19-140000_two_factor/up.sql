@@ -0,0 +1,21 @@
+-- a user's totp secret, which turns on the second login step
+CREATE TABLE two_factor(
+  user_id    INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  secret     TEXT NOT NULL,
+  -- the last time step a code was used in, so a code can't be used twice
+  last_step  BIGINT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE recovery_codes(
+  id        INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  user_id   INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL UNIQUE
+);
+
+-- settings for the whole instance, which only ever has the one row
+CREATE TABLE settings(
+  id                 INT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+  require_two_factor BOOLEAN NOT NULL DEFAULT FALSE
+);
+INSERT INTO settings DEFAULT VALUES;
@@ -0,0 +1,11 @@
+-- logins are looked up by the hash of the token in their cookie so they can be revoked
+CREATE TABLE sessions(
+  id           INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  user_id      INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  token_hash   TEXT NOT NULL UNIQUE,
+  created_at   TIMESTAMPTZ NOT NULL,
+  last_seen_at TIMESTAMPTZ NOT NULL,
+  ip           TEXT,
+  user_agent   TEXT
+);
+CREATE INDEX sessions_user_id ON sessions(user_id);
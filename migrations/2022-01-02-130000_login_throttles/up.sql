@@ -0,0 +1,10 @@
+-- failed logins are counted per username and per ip so guessing passwords gets slower
+CREATE TABLE login_throttles(
+  id              INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  kind            TEXT NOT NULL,
+  subject         TEXT NOT NULL,
+  failures        INT NOT NULL DEFAULT 0,
+  last_failure_at TIMESTAMPTZ NOT NULL,
+  locked_until    TIMESTAMPTZ,
+  UNIQUE (kind, subject)
+);
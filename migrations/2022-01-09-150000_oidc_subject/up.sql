@@ -0,0 +1,2 @@
+-- the identity provider's id for the user, so they stay linked if their username changes
+ALTER TABLE users ADD COLUMN oidc_subject TEXT UNIQUE;
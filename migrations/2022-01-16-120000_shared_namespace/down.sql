@@ -0,0 +1,4 @@
+DROP TRIGGER aliases_alias_not_short ON aliases;
+DROP TRIGGER links_short_not_alias ON links;
+DROP FUNCTION check_alias_not_short();
+DROP FUNCTION check_short_not_alias();
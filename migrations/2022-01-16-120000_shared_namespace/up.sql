@@ -0,0 +1,29 @@
+-- shorts and aliases share one namespace. each name is locked until the end of the
+-- transaction before it's checked, so concurrent inserts of the same name can't both pass
+CREATE FUNCTION check_short_not_alias() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_advisory_xact_lock(hashtext('linkr_names'), hashtext(NEW.short));
+  IF EXISTS (SELECT 1 FROM aliases WHERE alias = NEW.short) THEN
+    RAISE unique_violation USING MESSAGE = 'that short is already an alias';
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION check_alias_not_short() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_advisory_xact_lock(hashtext('linkr_names'), hashtext(NEW.alias));
+  IF EXISTS (SELECT 1 FROM links WHERE short = NEW.alias) THEN
+    RAISE unique_violation USING MESSAGE = 'that alias is already a short';
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER links_short_not_alias
+  BEFORE INSERT OR UPDATE OF short ON links
+  FOR EACH ROW EXECUTE FUNCTION check_short_not_alias();
+
+CREATE TRIGGER aliases_alias_not_short
+  BEFORE INSERT OR UPDATE OF alias ON aliases
+  FOR EACH ROW EXECUTE FUNCTION check_alias_not_short();
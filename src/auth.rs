@@ -15,49 +15,400 @@
 // You should have received a copy of the GNU General Public License
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
+use chrono::{DateTime, Duration, Utc};
 use diesel::result::Error;
-use rocket::http::{Cookie, Status};
+use diesel::QueryResult;
+use rocket::http::{Cookie, CookieJar, RawStr, Status};
 use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::Redirect;
+use rocket::State;
+use std::net::{IpAddr, Ipv6Addr};
+use time::OffsetDateTime;
 
+use crate::client::ClientInfo;
+use crate::config::LinkrConfig;
+use crate::crypto::{generate_token, hash_token};
 use crate::db::DbConn;
+use crate::models::login_throttles::{Backoff, LoginThrottle, ThrottleKind};
+use crate::models::sessions::{Expiry, NewSession, Session};
+use crate::models::settings::Settings;
+use crate::models::tokens::ApiToken;
+use crate::models::two_factor::TwoFactor;
 use crate::models::users::User;
 
+/// How long someone has to enter their code after their password, in seconds
+const SECOND_STEP_TIMEOUT: i64 = 5 * 60;
+
+/// How often a session's last seen time is updated, in seconds, so every request isn't a write
+const SESSION_TOUCH_INTERVAL: i64 = 60;
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for User {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        // get user_id from cookie
-        let user_id = match request
-            .cookies()
-            .get_private("user_id")
-            .and_then(|cookie| cookie.value().parse::<i32>().ok())
-        {
-            Some(id) => id,
-            None => return Outcome::Failure((Status::Unauthorized, ())),
-        };
-
         // get database connection
         let conn = request
             .guard::<DbConn>()
             .await
             .expect("database needs to be connected");
 
-        // get user from database with id and block if disabled
-        match User::get(user_id, &conn).await {
-            Ok(user) => {
-                if user.disabled {
-                    Outcome::Failure((Status::Unauthorized, ()))
-                } else {
-                    Outcome::Success(user)
-                }
-            }
-            Err(Error::NotFound) => {
-                // user id is not valid, so it mist be removed to prevent an infinite loop
-                request.cookies().remove_private(Cookie::named("user_id"));
+        // api tokens take priority over the cookie when they are sent
+        // other schemes are left alone, since a proxy in front of linkr may send its own
+        if let Some(token) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            return from_token(token, request, &conn).await;
+        }
+
+        let config = match request.guard::<&State<LinkrConfig>>().await.succeeded() {
+            Some(config) => config,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        from_cookies(request.cookies(), config, &conn).await
+    }
+}
+
+/// The user logged in with the browser's cookies
+pub async fn from_cookies(
+    cookies: &CookieJar<'_>,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> Outcome<User, ()> {
+    let session = match current_session(cookies, config, conn).await {
+        Ok(Some(session)) => session,
+        Ok(None) => return Outcome::Failure((Status::Unauthorized, ())),
+        Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+    };
+    if Utc::now() - session.last_seen_at > Duration::seconds(SESSION_TOUCH_INTERVAL)
+        && Session::touch(session.id, conn).await.is_err()
+    {
+        return Outcome::Failure((Status::InternalServerError, ()));
+    }
+
+    // get user from database with id and block if disabled
+    match User::get(session.user_id, conn).await {
+        // logged in before two factor was required of them, so they have to log in again to set it up
+        Ok(user) => match missing_two_factor(&user, conn).await {
+            Ok(false) => enabled_only(user),
+            Ok(true) => {
+                log_out(cookies, conn).await;
                 Outcome::Failure((Status::Unauthorized, ()))
             }
             Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+        },
+        Err(Error::NotFound) => Outcome::Failure((Status::Unauthorized, ())),
+        Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+    }
+}
+
+/// The session in the browser's cookie, if it's still active
+pub async fn current_session(
+    cookies: &CookieJar<'_>,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> QueryResult<Option<Session>> {
+    let token = match cookies.get_private("session") {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Ok(None),
+    };
+    match Session::get_active(hash_token(&token), session_expiry(config), conn).await {
+        Ok(session) => Ok(Some(session)),
+        Err(Error::NotFound) => {
+            // the session expired or was revoked, so the cookie is no use anymore
+            cookies.remove_private(Cookie::named("session"));
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn session_expiry(config: &LinkrConfig) -> Expiry {
+    let now = Utc::now();
+    Expiry {
+        idle_since: now - Duration::hours(config.session_idle_hours),
+        created_since: now - Duration::hours(config.session_max_hours),
+    }
+}
+
+async fn from_token(token: &str, request: &Request<'_>, conn: &DbConn) -> Outcome<User, ()> {
+    let api_token = match ApiToken::get_by_hash(hash_token(token), conn).await {
+        Ok(api_token) => api_token,
+        Err(Error::NotFound) => return Outcome::Failure((Status::Unauthorized, ())),
+        Err(_) => return Outcome::Failure((Status::InternalServerError, ())),
+    };
+
+    // block requests outside of what the token was scoped to
+    let allowed = api_token
+        .scope()
+        .map(|scope| scope.allows(request.method(), request.uri().path().as_str()))
+        .unwrap_or(false);
+    if !allowed {
+        return Outcome::Failure((Status::Forbidden, ()));
+    }
+
+    if ApiToken::touch(api_token.id, conn).await.is_err() {
+        return Outcome::Failure((Status::InternalServerError, ()));
+    }
+
+    match User::get(api_token.user_id, conn).await {
+        Ok(user) => enabled_only(user),
+        Err(Error::NotFound) => Outcome::Failure((Status::Unauthorized, ())),
+        Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+    }
+}
+
+/// Checks that a page to go back to after logging in stays on this site,
+/// so a crafted link can't be used to send someone elsewhere
+pub fn safe_return_to(next: &str) -> Option<&str> {
+    let on_site = next.starts_with('/')
+        && !next.starts_with("//")
+        && !next.contains('\\')
+        && !next.chars().any(char::is_control);
+    if on_site {
+        Some(next)
+    } else {
+        None
+    }
+}
+
+/// The login page, remembering where to go back to
+pub fn login_redirect(next: Option<&str>) -> Redirect {
+    Redirect::to(login_path(next))
+}
+
+/// The path to the login page, remembering where to go back to
+pub fn login_path(next: Option<&str>) -> String {
+    match next.and_then(safe_return_to) {
+        Some(next) => format!("/login?next={}", RawStr::new(next).percent_encode()),
+        None => String::from("/login"),
+    }
+}
+
+/// What's left to do after a user's password is checked
+pub enum SecondStep {
+    None,
+    /// Enter a code from their authenticator app or a recovery code
+    Code(TwoFactor),
+    /// Set up two factor, since it's required of them
+    Setup,
+}
+
+pub async fn second_step(user: &User, conn: &DbConn) -> QueryResult<SecondStep> {
+    if let Some(two_factor) = TwoFactor::get(user.id, conn).await? {
+        return Ok(SecondStep::Code(two_factor));
+    }
+    if missing_two_factor(user, conn).await? {
+        return Ok(SecondStep::Setup);
+    }
+    Ok(SecondStep::None)
+}
+
+/// Whether two factor is required of a user that hasn't set it up
+async fn missing_two_factor(user: &User, conn: &DbConn) -> QueryResult<bool> {
+    // most users can't be required to, so the settings aren't looked up for them
+    if !user.manage_links && !user.manage_users {
+        return Ok(false);
+    }
+    if !Settings::get(conn).await?.requires_two_factor(user) {
+        return Ok(false);
+    }
+    Ok(TwoFactor::get(user.id, conn).await?.is_none())
+}
+
+/// Starts a new session for the user in this browser
+pub async fn log_in(
+    user: &User,
+    client: &ClientInfo,
+    cookies: &CookieJar<'_>,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> QueryResult<()> {
+    let token = generate_token();
+    let now = Utc::now();
+    let session = NewSession {
+        user_id: user.id,
+        token_hash: hash_token(&token),
+        created_at: now,
+        last_seen_at: now,
+        ip: client.ip.map(|ip| ip.to_string()),
+        user_agent: client.user_agent.clone(),
+    };
+    Session::insert(session, conn).await?;
+    Session::delete_expired(session_expiry(config), conn).await?;
+
+    cookies.remove_private(Cookie::named("pending_login"));
+    let expires = OffsetDateTime::now_utc() + time::Duration::hours(config.session_max_hours);
+    cookies.add_private(Cookie::build("session", token).expires(expires).finish());
+    Ok(())
+}
+
+/// Ends the session in this browser
+pub async fn log_out(cookies: &CookieJar<'_>, conn: &DbConn) {
+    if let Some(cookie) = cookies.get_private("session") {
+        let hash = hash_token(cookie.value());
+        if let Err(err) = Session::revoke_by_hash(hash, conn).await {
+            warn!("Failed to revoke session: {}", err);
+        }
+    }
+    cookies.remove_private(Cookie::named("session"));
+    cookies.remove_private(Cookie::named("pending_login"));
+}
+
+/// Remembers whose password was just checked while they go through the second step
+pub fn start_second_step(cookies: &CookieJar<'_>, user_id: i32) {
+    let value = format!("{}:{}", user_id, Utc::now().timestamp());
+    cookies.add_private(Cookie::new("pending_login", value));
+}
+
+/// The user partway through logging in, if they haven't taken too long
+pub fn pending_login(cookies: &CookieJar<'_>) -> Option<i32> {
+    let cookie = cookies.get_private("pending_login")?;
+    let (user_id, started) = cookie.value().split_once(':')?;
+    let started: i64 = started.parse().ok()?;
+    if Utc::now().timestamp() - started > SECOND_STEP_TIMEOUT {
+        return None;
+    }
+    user_id.parse().ok()
+}
+
+/// The second login step, remembering where to go back to
+pub fn second_step_redirect(next: Option<&str>) -> Redirect {
+    match next.and_then(safe_return_to) {
+        Some(next) => Redirect::to(format!(
+            "/login/two_factor?next={}",
+            RawStr::new(next).percent_encode()
+        )),
+        None => Redirect::to("/login/two_factor"),
+    }
+}
+
+/* ------------------------------ failed logins ----------------------------- */
+
+/// What a login attempt counts against. A right password clears the username, but the ip only
+/// gets that one attempt back, so logging in to one account doesn't reset guesses made at others
+fn throttle_subjects(username: &str, client: &ClientInfo) -> Vec<(ThrottleKind, String)> {
+    let mut subjects = vec![(ThrottleKind::Username, username.to_string())];
+    if let Some(ip) = client.ip {
+        subjects.push((ThrottleKind::Ip, ip_subject(ip)));
+    }
+    subjects
+}
+
+/// The ip a failure counts against. Anyone with an ipv6 address usually has the whole /64
+/// around it, so those are counted together
+fn ip_subject(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.to_string(),
+            None => {
+                let network = u128::from(ip) & !((1u128 << 64) - 1);
+                format!("{}/64", Ipv6Addr::from(network))
+            }
+        },
+    }
+}
+
+/// What a guess at a link's password counts against. It's kept to the ip guessing so
+/// nobody else is locked out of the link
+fn unlock_subject(short: &str, client: &ClientInfo) -> (ThrottleKind, String) {
+    let subject = match client.ip {
+        Some(ip) => format!("{} from {}", short, ip_subject(ip)),
+        None => short.to_string(),
+    };
+    (ThrottleKind::Link, subject)
+}
+
+fn backoff(kind: ThrottleKind, config: &LinkrConfig) -> Backoff {
+    let max_failures = match kind {
+        ThrottleKind::Username | ThrottleKind::Link => config.login_max_failures,
+        ThrottleKind::Ip => config.login_max_ip_failures,
+    };
+    Backoff {
+        max_failures,
+        lockout: Duration::minutes(config.login_lockout_minutes),
+    }
+}
+
+/// Counts a login attempt as failed before its password or code is checked, so a burst of
+/// guesses can't all be checked before the first failure is counted. Gives why it can't be
+/// tried instead when there have been too many failures lately
+pub async fn reserve_login_attempt(
+    username: &str,
+    client: &ClientInfo,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> QueryResult<Option<String>> {
+    let subjects = throttle_subjects(username, client)
+        .into_iter()
+        .map(|(kind, subject)| (kind, subject, backoff(kind, config)))
+        .collect();
+    let locked_until = LoginThrottle::reserve(subjects, conn).await?;
+    Ok(locked_until.map(|locked_until| lockout_message("Too many failed logins", locked_until)))
+}
+
+/// Takes back a reserved login attempt that wasn't a wrong guess
+pub async fn release_login_attempt(
+    username: &str,
+    client: &ClientInfo,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> QueryResult<()> {
+    for (kind, subject) in throttle_subjects(username, client) {
+        match kind {
+            ThrottleKind::Username => {
+                LoginThrottle::clear(kind, subject, conn).await?;
+            }
+            ThrottleKind::Ip | ThrottleKind::Link => {
+                LoginThrottle::release(kind, subject, backoff(kind, config), conn).await?;
+            }
         }
     }
+    Ok(())
+}
+
+/// Counts a guess at a link's password as wrong before it's checked, the same way logins are
+pub async fn reserve_unlock_attempt(
+    short: &str,
+    client: &ClientInfo,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> QueryResult<Option<String>> {
+    let (kind, subject) = unlock_subject(short, client);
+    let locked_until =
+        LoginThrottle::reserve(vec![(kind, subject, backoff(kind, config))], conn).await?;
+    Ok(locked_until.map(|locked_until| lockout_message("Too many wrong passwords", locked_until)))
+}
+
+/// Forgets the wrong guesses at a link once its password is right
+pub async fn release_unlock_attempt(
+    short: &str,
+    client: &ClientInfo,
+    conn: &DbConn,
+) -> QueryResult<()> {
+    let (kind, subject) = unlock_subject(short, client);
+    LoginThrottle::clear(kind, subject, conn).await?;
+    Ok(())
+}
+
+fn lockout_message(reason: &str, locked_until: DateTime<Utc>) -> String {
+    let seconds = (locked_until - Utc::now()).num_seconds().max(1);
+    let wait = match seconds {
+        1 => String::from("a second"),
+        2..=59 => format!("{} seconds", seconds),
+        60 => String::from("a minute"),
+        _ => format!("{} minutes", (seconds + 59) / 60),
+    };
+    format!("{}, try again in {}", reason, wait)
+}
+
+fn enabled_only(user: User) -> Outcome<User, ()> {
+    if user.disabled {
+        Outcome::Failure((Status::Unauthorized, ()))
+    } else {
+        Outcome::Success(user)
+    }
 }
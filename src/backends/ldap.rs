@@ -0,0 +1,238 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+use std::time::Duration;
+
+use ldap3::{dn_escape, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use rocket_sync_db_pools::diesel::result::{DatabaseErrorKind, Error};
+
+use super::{AuthBackend, AuthError, AuthResult, INTERNAL};
+use crate::config::LinkrConfig;
+use crate::db::DbConn;
+use crate::models::users::{InsertableUser, User};
+
+/// How long to wait on the directory before giving up
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The result code binding with the wrong password gives
+const INVALID_CREDENTIALS: u32 = 49;
+
+const UNREACHABLE: &str = "The directory could not be reached";
+
+/// Checks passwords by binding to an ldap or active directory server as the person logging in.
+/// They're looked up by username first, so their dn doesn't have to follow a pattern.
+pub struct LdapBackend {
+    url: String,
+    starttls: bool,
+    bind_dn: Option<String>,
+    bind_password: String,
+    base_dn: String,
+    user_filter: String,
+    username_attribute: String,
+    group_base_dn: String,
+    group_filter: String,
+    link_groups: Vec<String>,
+    user_groups: Vec<String>,
+    create_users: bool,
+}
+
+/// Someone the directory vouched for
+struct Person {
+    username: String,
+    groups: Vec<String>,
+}
+
+impl LdapBackend {
+    pub fn new(config: &LinkrConfig) -> LdapBackend {
+        LdapBackend {
+            url: config.ldap_url.clone(),
+            starttls: config.ldap_starttls,
+            bind_dn: config.ldap_bind_dn.clone(),
+            bind_password: config.ldap_bind_password.clone(),
+            base_dn: config.ldap_base_dn.clone(),
+            user_filter: config.ldap_user_filter.clone(),
+            username_attribute: config.ldap_username_attribute.clone(),
+            group_base_dn: config
+                .ldap_group_base_dn
+                .clone()
+                .unwrap_or_else(|| config.ldap_base_dn.clone()),
+            group_filter: config.ldap_group_filter.clone(),
+            link_groups: config.ldap_link_groups.clone(),
+            user_groups: config.ldap_user_groups.clone(),
+            create_users: config.ldap_create_users,
+        }
+    }
+
+    async fn connect(&self) -> AuthResult<Ldap> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(TIMEOUT)
+            .set_starttls(self.starttls);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(|_| AuthError::Failed(UNREACHABLE))?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    /// Binds as someone, telling a wrong password apart from the directory having a problem
+    async fn bind(ldap: &mut Ldap, dn: &str, password: &str) -> AuthResult<()> {
+        let result = ldap
+            .with_timeout(TIMEOUT)
+            .simple_bind(dn, password)
+            .await
+            .map_err(|_| AuthError::Failed(UNREACHABLE))?;
+        match result.rc {
+            0 => Ok(()),
+            INVALID_CREDENTIALS => Err(AuthError::Invalid),
+            _ => Err(AuthError::Failed(UNREACHABLE)),
+        }
+    }
+
+    async fn search(
+        ldap: &mut Ldap,
+        base: &str,
+        filter: &str,
+        attrs: Vec<&str>,
+    ) -> AuthResult<Vec<SearchEntry>> {
+        let (entries, _) = ldap
+            .with_timeout(TIMEOUT)
+            .search(base, Scope::Subtree, filter, attrs)
+            .await
+            .and_then(|result| result.success())
+            .map_err(|_| AuthError::Failed(UNREACHABLE))?;
+        Ok(entries.into_iter().map(SearchEntry::construct).collect())
+    }
+
+    /// Finds someone by username and checks their password, along with their groups
+    async fn find_person(&self, username: &str, password: &str) -> AuthResult<Person> {
+        let mut ldap = self.connect().await?;
+        if let Some(bind_dn) = &self.bind_dn {
+            // a wrong service account password is the server's problem, not the person logging in's
+            LdapBackend::bind(&mut ldap, bind_dn, &self.bind_password)
+                .await
+                .map_err(|_| AuthError::Failed(UNREACHABLE))?;
+        }
+
+        let filter = format!(
+            "(&{}({}={}))",
+            self.user_filter,
+            self.username_attribute,
+            ldap_escape(username)
+        );
+        let entries = LdapBackend::search(
+            &mut ldap,
+            &self.base_dn,
+            &filter,
+            vec![self.username_attribute.as_str()],
+        )
+        .await?;
+        // more than one match can't be told apart, so neither is logged in to
+        let entry = match entries.as_slice() {
+            [entry] => entry,
+            _ => {
+                // bind anyway so unknown usernames take as long as wrong passwords
+                let dn = format!(
+                    "{}={},{}",
+                    self.username_attribute,
+                    dn_escape(username),
+                    self.base_dn
+                );
+                let _ = LdapBackend::bind(&mut ldap, &dn, password).await;
+                return Err(AuthError::Invalid);
+            }
+        };
+        LdapBackend::bind(&mut ldap, &entry.dn, password).await?;
+
+        // the directory's spelling, since it matches usernames without caring about case
+        let username = entry
+            .attrs
+            .get(&self.username_attribute)
+            .and_then(|values| values.first())
+            .cloned()
+            .unwrap_or_else(|| username.to_string());
+        let filter = self
+            .group_filter
+            .replace("{dn}", &ldap_escape(entry.dn.as_str()))
+            .replace("{username}", &ldap_escape(username.as_str()));
+        let groups = LdapBackend::search(&mut ldap, &self.group_base_dn, &filter, vec!["cn"])
+            .await?
+            .into_iter()
+            .filter_map(|group| group.attrs.get("cn").and_then(|cn| cn.first()).cloned())
+            .collect();
+        let _ = ldap.unbind().await;
+
+        Ok(Person { username, groups })
+    }
+
+    /// The permissions someone's groups give them as `(manage_links, manage_users)`, unless
+    /// permissions aren't mapped from groups
+    fn permissions(&self, person: &Person) -> Option<(bool, bool)> {
+        if self.link_groups.is_empty() && self.user_groups.is_empty() {
+            return None;
+        }
+        let in_any = |groups: &[String]| person.groups.iter().any(|group| groups.contains(group));
+        Some((in_any(&self.link_groups), in_any(&self.user_groups)))
+    }
+}
+
+#[rocket::async_trait]
+impl AuthBackend for LdapBackend {
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+        conn: &DbConn,
+    ) -> AuthResult<User> {
+        // binding with no password is an anonymous bind, which would always work
+        if username.is_empty() || password.is_empty() {
+            return Err(AuthError::Invalid);
+        }
+        let person = self.find_person(username, password).await?;
+        let permissions = self.permissions(&person);
+
+        // accounts are tied to the directory by username
+        let mut user = match User::get_by_name(person.username.clone(), conn).await {
+            Ok(user) => user,
+            Err(Error::NotFound) if self.create_users => {
+                let new_user = InsertableUser::external(
+                    person.username,
+                    permissions.unwrap_or((false, false)),
+                    None,
+                );
+                return match User::insert(new_user, conn).await {
+                    Ok(user) => Ok(user),
+                    Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                        Err(AuthError::Failed("That username belongs to someone else"))
+                    }
+                    Err(_) => Err(AuthError::Failed(INTERNAL)),
+                };
+            }
+            Err(Error::NotFound) => {
+                return Err(AuthError::Failed(
+                    "You don't have an account yet, ask an admin to make one",
+                ))
+            }
+            Err(_) => return Err(AuthError::Failed(INTERNAL)),
+        };
+
+        if let Some(permissions) = permissions {
+            user.sync_permissions(permissions, conn)
+                .await
+                .map_err(|_| AuthError::Failed(INTERNAL))?;
+        }
+        Ok(user)
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+use rocket_sync_db_pools::diesel::result::Error;
+
+use super::{AuthBackend, AuthError, AuthResult, INTERNAL};
+use crate::crypto::verify_missing_user;
+use crate::db::DbConn;
+use crate::models::users::User;
+
+/// Passwords hashed with scrypt in the users table
+pub struct LocalBackend;
+
+#[rocket::async_trait]
+impl AuthBackend for LocalBackend {
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+        conn: &DbConn,
+    ) -> AuthResult<User> {
+        match User::get_by_name(username.to_string(), conn).await {
+            Ok(user) if user.verify(password) => Ok(user),
+            Ok(_) => Err(AuthError::Invalid),
+            // still hashed, so usernames that don't exist take as long to fail as the wrong password
+            Err(Error::NotFound) => {
+                verify_missing_user(password);
+                Err(AuthError::Invalid)
+            }
+            Err(_) => Err(AuthError::Failed(INTERNAL)),
+        }
+    }
+
+    fn stores_passwords(&self) -> bool {
+        true
+    }
+}
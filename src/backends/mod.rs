@@ -0,0 +1,83 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+use rocket::serde::Deserialize;
+
+use crate::config::LinkrConfig;
+use crate::db::DbConn;
+use crate::models::users::User;
+
+mod ldap;
+mod local;
+
+pub use ldap::LdapBackend;
+pub use local::LocalBackend;
+
+const INTERNAL: &str = "An internal server error occurred";
+
+/// Which backend checks passwords, set with `auth_backend`
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Local,
+    Ldap,
+}
+
+pub enum AuthError {
+    /// the username or password was wrong, which counts toward a lockout
+    Invalid,
+    /// anything else that kept someone from logging in, with a message to show them
+    Failed(&'static str),
+}
+
+pub type AuthResult<T> = Result<T, AuthError>;
+
+/// Somewhere usernames and passwords can be checked
+#[rocket::async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// Checks a username and password, giving back the account they log in to
+    async fn authenticate(&self, username: &str, password: &str, conn: &DbConn)
+        -> AuthResult<User>;
+
+    /// Whether passwords are kept in linkr, so they can be changed here
+    fn stores_passwords(&self) -> bool {
+        false
+    }
+}
+
+/// The backend routes get as managed state
+pub type Backend = Box<dyn AuthBackend>;
+
+pub fn from_config(config: &LinkrConfig) -> Backend {
+    match config.auth_backend {
+        BackendKind::Local => Box::new(LocalBackend),
+        BackendKind::Ldap => Box::new(LdapBackend::new(config)),
+    }
+}
+
+/// Checks a password again for an account that's already logged in, like before deleting it
+pub async fn confirm_password(
+    backend: &Backend,
+    user: &User,
+    password: &str,
+    conn: &DbConn,
+) -> AuthResult<()> {
+    match backend.authenticate(&user.username, password, conn).await? {
+        confirmed if confirmed.id == user.id => Ok(()),
+        _ => Err(AuthError::Invalid),
+    }
+}
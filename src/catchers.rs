@@ -15,11 +15,15 @@
 // You should have received a copy of the GNU General Public License
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
+use rocket::http::Method;
 use rocket::response::Redirect;
 use rocket::serde::json::serde_json::json;
 use rocket::serde::Serialize;
+use rocket::Request;
 use rocket_dyn_templates::Template;
 
+use crate::auth::login_redirect;
+
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
 struct ErrorContext {
@@ -28,9 +32,15 @@ struct ErrorContext {
     details: &'static str,
 }
 
+/// Sends the user to log in, coming back to the page they were trying to see
 #[catch(401)]
-pub fn unauthorized() -> Redirect {
-    Redirect::to("/login")
+pub fn unauthorized(request: &Request) -> Redirect {
+    // only pages can be returned to, not form submissions
+    if request.method() != Method::Get {
+        return login_redirect(None);
+    }
+    let next = request.uri().to_string();
+    login_redirect(Some(&next))
 }
 
 #[catch(403)]
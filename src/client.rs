@@ -0,0 +1,78 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::State;
+use std::net::IpAddr;
+
+use crate::config::LinkrConfig;
+
+/// Details about who made a request, used for analytics and login throttling
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = |name| request.headers().get_one(name).map(String::from);
+
+        // anyone can send `X-Real-IP`, so it's only believed when a known proxy passed it on
+        let remote = request.remote().map(|addr| addr.ip());
+        let config = request.guard::<&State<LinkrConfig>>().await;
+        let ip = match (remote, config.succeeded()) {
+            (Some(remote), Some(config)) if config.trusted_proxies.contains(&remote) => {
+                request.real_ip().or(Some(remote))
+            }
+            _ => remote,
+        };
+
+        Outcome::Success(ClientInfo {
+            ip,
+            referrer: header("Referer"),
+            user_agent: header("User-Agent"),
+        })
+    }
+}
+
+/// Where Linkr is being reached from, used to write out full short urls.
+/// Falls back to the request's host when `public_url` isn't configured.
+pub struct BaseUrl(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BaseUrl {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.guard::<&State<LinkrConfig>>().await;
+        if let Some(public_url) = config
+            .succeeded()
+            .and_then(|config| config.public_url.as_ref())
+        {
+            return Outcome::Success(BaseUrl(public_url.trim_end_matches('/').to_string()));
+        }
+
+        let headers = request.headers();
+        let scheme = headers.get_one("X-Forwarded-Proto").unwrap_or("http");
+        let host = headers.get_one("Host").unwrap_or("localhost");
+        Outcome::Success(BaseUrl(format!("{}://{}", scheme, host)))
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use rocket::serde::Deserialize;
+use std::net::IpAddr;
+
+use crate::backends::BackendKind;
+use crate::models::links::REDIRECT_CODES;
+
+/// Instance-wide settings read from the Rocket figment (`Rocket.toml` or
+/// `ROCKET_`-prefixed environment variables)
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LinkrConfig {
+    /// mixed into client ips before they are hashed for click analytics
+    #[serde(default)]
+    pub ip_hash_salt: String,
+
+    /// characters that randomly generated shorts are made of
+    #[serde(default = "default_random_short_alphabet")]
+    pub random_short_alphabet: String,
+
+    /// the shortest a randomly generated short can be
+    #[serde(default = "default_random_short_length")]
+    pub random_short_length: usize,
+
+    /// leave characters that are easily confused (like `0` and `O`) out of random shorts
+    #[serde(default)]
+    pub random_short_exclude_lookalikes: bool,
+
+    /// the http status new links redirect with when one isn't picked
+    #[serde(default = "default_redirect_code")]
+    pub default_redirect_code: i16,
+
+    /// the address short links are shared under, like `https://example.com`
+    #[serde(default)]
+    pub public_url: Option<String>,
+
+    /// reverse proxies whose `X-Real-IP` header is believed. requests from anywhere else are
+    /// taken to come from the address they were made from
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// domains (and their subdomains) links go to without a warning first. when
+    /// this is empty no warnings are shown
+    #[serde(default)]
+    pub trusted_domains: Vec<String>,
+
+    /// how many seconds the leaving warning waits before moving on, or 0 to wait for a click
+    #[serde(default = "default_leaving_delay")]
+    pub leaving_delay: u32,
+
+    /// hours a login lasts without being used
+    #[serde(default = "default_session_idle_hours")]
+    pub session_idle_hours: i64,
+
+    /// hours a login lasts at most, even while it's being used
+    #[serde(default = "default_session_max_hours")]
+    pub session_max_hours: i64,
+
+    /// failed logins for a username before it's locked out
+    #[serde(default = "default_login_max_failures")]
+    pub login_max_failures: i32,
+
+    /// failed logins from an ip before it's locked out. higher than for usernames since many
+    /// people can share one
+    #[serde(default = "default_login_max_ip_failures")]
+    pub login_max_ip_failures: i32,
+
+    /// minutes a lockout lasts, which is also the longest wait between failed logins
+    #[serde(default = "default_login_lockout_minutes")]
+    pub login_lockout_minutes: i64,
+
+    /// the identity provider to log in with, like `https://accounts.example.com`. single sign-on
+    /// is off without it
+    #[serde(default)]
+    pub oidc_issuer: Option<String>,
+
+    /// what the identity provider knows linkr as
+    #[serde(default)]
+    pub oidc_client_id: String,
+
+    /// left out for identity providers that treat linkr as a public client
+    #[serde(default)]
+    pub oidc_client_secret: Option<String>,
+
+    /// what the login button calls the identity provider
+    #[serde(default = "default_oidc_name")]
+    pub oidc_name: String,
+
+    /// scopes asked for when logging in, separated by spaces
+    #[serde(default = "default_oidc_scopes")]
+    pub oidc_scopes: String,
+
+    /// the id token claim usernames come from
+    #[serde(default = "default_oidc_username_claim")]
+    pub oidc_username_claim: String,
+
+    /// the id token claim groups come from
+    #[serde(default = "default_oidc_groups_claim")]
+    pub oidc_groups_claim: String,
+
+    /// groups that can manage all links. when this and `oidc_user_groups` are both empty,
+    /// permissions are left to be managed in linkr
+    #[serde(default)]
+    pub oidc_link_groups: Vec<String>,
+
+    /// groups that can manage users
+    #[serde(default)]
+    pub oidc_user_groups: Vec<String>,
+
+    /// make accounts for people the first time they log in with the identity provider
+    #[serde(default)]
+    pub oidc_create_users: bool,
+
+    /// only allow logging in with the identity provider
+    #[serde(default)]
+    pub disable_passwords: bool,
+
+    /// where usernames and passwords are checked: `local` or `ldap`
+    #[serde(default)]
+    pub auth_backend: BackendKind,
+
+    /// the directory server, like `ldap://localhost:389` or `ldaps://ldap.example.com`
+    #[serde(default = "default_ldap_url")]
+    pub ldap_url: String,
+
+    /// upgrade `ldap://` connections with starttls
+    #[serde(default)]
+    pub ldap_starttls: bool,
+
+    /// who to bind as to look people up. they're looked up anonymously without it
+    #[serde(default)]
+    pub ldap_bind_dn: Option<String>,
+
+    #[serde(default)]
+    pub ldap_bind_password: String,
+
+    /// where people are looked up, like `ou=people,dc=example,dc=org`
+    #[serde(default)]
+    pub ldap_base_dn: String,
+
+    /// which entries under the base dn can log in
+    #[serde(default = "default_ldap_user_filter")]
+    pub ldap_user_filter: String,
+
+    /// the attribute usernames are matched against, like `uid` or `sAMAccountName`
+    #[serde(default = "default_ldap_username_attribute")]
+    pub ldap_username_attribute: String,
+
+    /// where groups are looked up. defaults to the base dn
+    #[serde(default)]
+    pub ldap_group_base_dn: Option<String>,
+
+    /// finds someone's groups, with `{dn}` and `{username}` filled in for them
+    #[serde(default = "default_ldap_group_filter")]
+    pub ldap_group_filter: String,
+
+    /// groups (by `cn`) that can manage all links. when this and `ldap_user_groups` are both
+    /// empty, permissions are left to be managed in linkr
+    #[serde(default)]
+    pub ldap_link_groups: Vec<String>,
+
+    /// groups that can manage users
+    #[serde(default)]
+    pub ldap_user_groups: Vec<String>,
+
+    /// make accounts for people the first time they log in from the directory
+    #[serde(default)]
+    pub ldap_create_users: bool,
+}
+
+impl LinkrConfig {
+    /// Catches settings that would make requests fail later on
+    pub fn validate(&self) -> Result<(), String> {
+        if !REDIRECT_CODES.contains(&self.default_redirect_code) {
+            return Err(format!(
+                "default_redirect_code must be one of {:?}, not {}",
+                REDIRECT_CODES, self.default_redirect_code
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_random_short_alphabet() -> String {
+    String::from("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789")
+}
+
+fn default_random_short_length() -> usize {
+    5
+}
+
+fn default_redirect_code() -> i16 {
+    307
+}
+
+fn default_leaving_delay() -> u32 {
+    5
+}
+
+fn default_session_idle_hours() -> i64 {
+    24 * 7
+}
+
+fn default_session_max_hours() -> i64 {
+    24 * 30
+}
+
+fn default_login_max_failures() -> i32 {
+    10
+}
+
+fn default_login_max_ip_failures() -> i32 {
+    50
+}
+
+fn default_login_lockout_minutes() -> i64 {
+    15
+}
+
+fn default_oidc_name() -> String {
+    String::from("Single Sign-On")
+}
+
+fn default_oidc_scopes() -> String {
+    String::from("openid profile email")
+}
+
+fn default_oidc_username_claim() -> String {
+    String::from("preferred_username")
+}
+
+fn default_oidc_groups_claim() -> String {
+    String::from("groups")
+}
+
+fn default_ldap_url() -> String {
+    String::from("ldap://localhost:389")
+}
+
+fn default_ldap_user_filter() -> String {
+    String::from("(objectClass=person)")
+}
+
+fn default_ldap_username_attribute() -> String {
+    String::from("uid")
+}
+
+fn default_ldap_group_filter() -> String {
+    String::from("(member={dn})")
+}
@@ -15,13 +15,17 @@
 // You should have received a copy of the GNU General Public License
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::models::links::Link;
 use crate::models::users::{InsertableUser, User};
 use crate::routes::users::NewUser;
-use rand_core::OsRng;
+use rand_core::{OsRng, RngCore};
 use scrypt::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Scrypt,
 };
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::OnceLock;
 
 impl InsertableUser {
     pub fn new_from_plain(new_user: NewUser, orig: bool) -> InsertableUser {
@@ -31,6 +35,24 @@ impl InsertableUser {
             orig,
             manage_links: new_user.manage_links,
             manage_users: new_user.manage_users,
+            oidc_subject: None,
+        }
+    }
+
+    /// An account for someone an identity provider or directory vouched for. Nobody knows its
+    /// password, so it can only be logged in to from there
+    pub fn external(
+        username: String,
+        (manage_links, manage_users): (bool, bool),
+        oidc_subject: Option<String>,
+    ) -> InsertableUser {
+        InsertableUser {
+            username,
+            pw_hash: encrypt_pw(&generate_token()),
+            orig: false,
+            manage_links,
+            manage_users,
+            oidc_subject,
         }
     }
 }
@@ -51,3 +73,85 @@ impl User {
             .is_ok()
     }
 }
+
+/// Checks a password against a throwaway hash, so logins with usernames that don't exist take
+/// as long to fail as ones with the wrong password
+pub fn verify_missing_user(password: &str) {
+    static THROWAWAY_HASH: OnceLock<String> = OnceLock::new();
+    let pw_hash = THROWAWAY_HASH.get_or_init(|| encrypt_pw(""));
+    let parsed_hash = PasswordHash::new(pw_hash).unwrap();
+    let _ = Scrypt.verify_password(password.as_bytes(), &parsed_hash);
+}
+
+impl Link {
+    pub fn verify_password(&self, password: &str) -> bool {
+        let pw_hash = match &self.pw_hash {
+            Some(pw_hash) => pw_hash,
+            None => return true,
+        };
+        match PasswordHash::new(pw_hash) {
+            Ok(parsed_hash) => Scrypt
+                .verify_password(password.as_bytes(), &parsed_hash)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Remembers that a browser unlocked the link, until its password changes
+    pub fn unlock_token(&self) -> Option<String> {
+        self.pw_hash.as_deref().map(hash_token)
+    }
+}
+
+/// Checks that an imported hash is one that passwords can be verified against
+pub fn is_pw_hash(pw_hash: &str) -> bool {
+    PasswordHash::new(pw_hash).is_ok()
+}
+
+pub fn hash_ip(ip: &IpAddr, salt: &str) -> String {
+    let digest = Sha256::new()
+        .chain(salt.as_bytes())
+        .chain(ip.to_string().as_bytes())
+        .finalize();
+    format!("{:x}", digest)
+}
+
+/// A random token to hand out to a user, prefixed so it's recognizable if leaked
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("linkr_{}", hex)
+}
+
+/// A one time code for logging in without the second factor, like `abcd-efgh-ijkl-mnop`
+pub fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: Vec<char> = bytes
+        .iter()
+        .map(|byte| ALPHABET[(*byte as usize) % ALPHABET.len()] as char)
+        .collect();
+    chars
+        .chunks(4)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Recovery codes are hashed like tokens, after the dashes and case someone types them with
+/// are taken out
+pub fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&code)
+}
+
+/// Tokens are long and random, so a fast hash is enough for storing them
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
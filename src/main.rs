@@ -24,17 +24,24 @@ extern crate rocket_sync_db_pools;
 #[macro_use]
 extern crate diesel_migrations;
 
-use rocket::fairing::AdHoc;
+use rocket::fairing::{self, AdHoc};
 use rocket::{Build, Rocket};
 use rocket_dyn_templates::Template;
 
 mod auth;
+mod backends;
 mod catchers;
+mod client;
+mod config;
 mod crypto;
 mod db;
 mod models;
+mod oidc;
+mod random_short;
 mod routes;
 mod schema;
+mod totp;
+mod url_template;
 
 #[launch]
 fn rocket() -> Rocket<Build> {
@@ -43,14 +50,21 @@ fn rocket() -> Rocket<Build> {
         .attach(db::DbConn::fairing())
         .attach(AdHoc::on_ignite("Database Migrations", run_db_migrations))
         .attach(Template::fairing())
+        .attach(AdHoc::config::<config::LinkrConfig>())
+        .attach(AdHoc::try_on_ignite("Config Validation", validate_config))
+        .attach(AdHoc::on_ignite("Authentication Backend", manage_backend))
         .mount(
             "/",
             routes![
                 routes::pages::link,
+                routes::pages::prefix_link,
+                routes::pages::preview,
                 routes::pages::index,
+                routes::pages::link_details,
                 routes::pages::new_user,
                 routes::pages::setup,
                 routes::pages::login,
+                routes::pages::login_two_factor,
                 routes::pages::manage_links,
                 routes::pages::manage_users,
                 routes::pages::manage_account,
@@ -62,24 +76,76 @@ fn rocket() -> Rocket<Build> {
             routes![
                 routes::links::shorten,
                 routes::links::delete,
-                routes::links::update
+                routes::links::update,
+                routes::links::edit,
+                routes::links::unlock,
+                routes::links::add_alias,
+                routes::links::remove_alias,
+                routes::links::update_redirect_code,
+                routes::links::update_tags,
+                routes::links::update_schedule,
+                routes::import_export::export,
+                routes::import_export::import,
+                routes::qr::qr
             ],
         )
+        .mount(
+            "/api/v1/links",
+            routes![
+                routes::api::links::list,
+                routes::api::links::get,
+                routes::api::links::create,
+                routes::api::links::update,
+                routes::api::links::delete,
+                routes::api::links::aliases,
+                routes::api::links::add_alias,
+                routes::api::links::remove_alias
+            ],
+        )
+        .mount(
+            "/api/tokens/",
+            routes![routes::tokens::new, routes::tokens::revoke],
+        )
         .mount(
             "/api/users/",
             routes![
                 routes::users::new,
                 routes::users::login,
                 routes::users::logout,
+                routes::users::revoke_session,
+                routes::users::revoke_all_sessions,
                 routes::users::delete_current,
                 routes::users::disable_current,
                 routes::users::delete_by_id,
                 routes::users::disable_by_id,
                 routes::users::enable_by_id,
+                routes::users::clear_lockout,
                 routes::users::update_permissions,
                 routes::users::update_own_username,
                 routes::users::update_username,
-                routes::users::update_password
+                routes::users::update_password,
+                routes::users::update_oidc_subject
+            ],
+        )
+        .mount(
+            "/api/oidc/",
+            routes![
+                routes::oidc::login,
+                routes::oidc::connect,
+                routes::oidc::callback,
+                routes::oidc::finish
+            ],
+        )
+        .mount(
+            "/api/users/two_factor/",
+            routes![
+                routes::two_factor::login,
+                routes::two_factor::start,
+                routes::two_factor::cancel,
+                routes::two_factor::confirm,
+                routes::two_factor::regenerate_recovery_codes,
+                routes::two_factor::disable,
+                routes::two_factor::require
             ],
         )
         .register(
@@ -92,6 +158,7 @@ fn rocket() -> Rocket<Build> {
                 catchers::service_unavailable
             ],
         )
+        .register("/api/v1", catchers![routes::api::default_catcher])
 }
 
 async fn run_db_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
@@ -109,3 +176,28 @@ async fn run_db_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
 
     rocket
 }
+
+/// Refuses to launch with settings that would break requests
+async fn validate_config(rocket: Rocket<Build>) -> fairing::Result {
+    let result = rocket
+        .state::<config::LinkrConfig>()
+        .map_or(Ok(()), config::LinkrConfig::validate);
+    match result {
+        Ok(()) => Ok(rocket),
+        Err(msg) => {
+            error!("Invalid configuration: {}", msg);
+            Err(rocket)
+        }
+    }
+}
+
+/// Picks where passwords are checked, once the config has been read
+async fn manage_backend(rocket: Rocket<Build>) -> Rocket<Build> {
+    let backend = rocket
+        .state::<config::LinkrConfig>()
+        .map(backends::from_config);
+    match backend {
+        Some(backend) => rocket.manage(backend),
+        None => rocket,
+    }
+}
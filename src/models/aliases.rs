@@ -0,0 +1,90 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+use rocket_sync_db_pools::diesel::result::Error;
+
+use crate::db::DbConn;
+use crate::models::formatters::date_format;
+use crate::schema::aliases;
+
+/// Another short that redirects the same way as the link it points to
+#[derive(Queryable, Insertable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[table_name = "aliases"]
+pub struct Alias {
+    pub alias: String,
+    pub short: String,
+    #[serde(with = "date_format")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Alias {
+    pub fn new(alias: String, short: String) -> Alias {
+        Alias {
+            alias,
+            short,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn all_for_link(short: String, db: &DbConn) -> QueryResult<Vec<Alias>> {
+        db.run(move |conn| {
+            aliases::table
+                .filter(aliases::short.eq(short))
+                .order(aliases::alias)
+                .load(conn)
+        })
+        .await
+    }
+
+    pub async fn count(db: &DbConn) -> QueryResult<i64> {
+        db.run(move |conn| aliases::table.count().get_result(conn))
+            .await
+    }
+
+    pub async fn insert(alias: Alias, db: &DbConn) -> QueryResult<Alias> {
+        // shorts and aliases share one namespace, which the database enforces
+        db.run(move |conn| {
+            diesel::insert_into(aliases::table)
+                .values(&alias)
+                .get_result(conn)
+        })
+        .await
+    }
+
+    /// Removes an alias from the link it points to
+    pub async fn delete(alias: String, short: String, db: &DbConn) -> QueryResult<usize> {
+        let deleted = db
+            .run(move |conn| {
+                diesel::delete(
+                    aliases::table
+                        .filter(aliases::alias.eq(alias))
+                        .filter(aliases::short.eq(short)),
+                )
+                .execute(conn)
+            })
+            .await?;
+        if deleted == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(deleted)
+    }
+}
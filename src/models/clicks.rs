@@ -0,0 +1,151 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+use rocket_sync_db_pools::diesel::sql_types::{Array, BigInt, Date, Integer, Nullable, Text};
+use std::collections::HashMap;
+
+use crate::client::ClientInfo;
+use crate::crypto::hash_ip;
+use crate::db::DbConn;
+use crate::schema::clicks;
+
+#[derive(Insertable)]
+#[table_name = "clicks"]
+pub struct Click {
+    pub short: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_hash: Option<String>,
+    pub via: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct ClickTotal {
+    #[sql_type = "Text"]
+    short: String,
+    #[sql_type = "BigInt"]
+    clicks: i64,
+}
+
+/// Clicks on a link split up by the short they came in through
+#[derive(QueryableByName, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ViaClicks {
+    /// `None` when the link's own short was used
+    #[sql_type = "Nullable<Text>"]
+    pub via: Option<String>,
+    #[sql_type = "BigInt"]
+    pub clicks: i64,
+}
+
+#[derive(QueryableByName, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DailyClicks {
+    #[sql_type = "Date"]
+    pub day: NaiveDate,
+    #[sql_type = "BigInt"]
+    pub clicks: i64,
+}
+
+impl Click {
+    pub fn new(short: String, via: Option<String>, client: ClientInfo, ip_salt: &str) -> Click {
+        Click {
+            short,
+            via,
+            clicked_at: Utc::now(),
+            referrer: client.referrer,
+            user_agent: client.user_agent,
+            ip_hash: client.ip.map(|ip| hash_ip(&ip, ip_salt)),
+        }
+    }
+
+    pub async fn record(click: Click, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::insert_into(clicks::table)
+                .values(&click)
+                .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn count(short: String, db: &DbConn) -> QueryResult<i64> {
+        db.run(move |conn| {
+            clicks::table
+                .filter(clicks::short.eq(short))
+                .count()
+                .get_result(conn)
+        })
+        .await
+    }
+
+    pub async fn by_via(short: String, db: &DbConn) -> QueryResult<Vec<ViaClicks>> {
+        db.run(move |conn| {
+            diesel::sql_query(
+                "SELECT via, COUNT(*) AS clicks FROM clicks \
+                 WHERE short = $1 \
+                 GROUP BY via \
+                 ORDER BY clicks DESC",
+            )
+            .bind::<Text, _>(short)
+            .load::<ViaClicks>(conn)
+        })
+        .await
+    }
+
+    /// Total clicks for each of the given links that has been clicked at least once
+    pub async fn totals(shorts: Vec<String>, db: &DbConn) -> QueryResult<HashMap<String, i64>> {
+        let totals = db
+            .run(move |conn| {
+                diesel::sql_query(
+                    "SELECT short, COUNT(*) AS clicks FROM clicks \
+                     WHERE short = ANY($1) \
+                     GROUP BY short",
+                )
+                .bind::<Array<Text>, _>(shorts)
+                .load::<ClickTotal>(conn)
+            })
+            .await?;
+        Ok(totals.into_iter().map(|t| (t.short, t.clicks)).collect())
+    }
+
+    /// Clicks per day for the last `days` days (including today), with empty days filled in
+    pub async fn daily(short: String, days: i32, db: &DbConn) -> QueryResult<Vec<DailyClicks>> {
+        db.run(move |conn| {
+            diesel::sql_query(
+                "SELECT day::date AS day, COUNT(clicks.id) AS clicks \
+                 FROM generate_series( \
+                     date_trunc('day', now()) - ($2 - 1) * interval '1 day', \
+                     date_trunc('day', now()), \
+                     interval '1 day' \
+                 ) AS day \
+                 LEFT JOIN clicks \
+                     ON clicks.short = $1 AND date_trunc('day', clicks.clicked_at) = day \
+                 GROUP BY day \
+                 ORDER BY day",
+            )
+            .bind::<Text, _>(short)
+            .bind::<Integer, _>(days)
+            .load::<DailyClicks>(conn)
+        })
+        .await
+    }
+}
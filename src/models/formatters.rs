@@ -0,0 +1,79 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+pub mod date_format {
+    use chrono::{DateTime, Utc};
+    use rocket::serde::Serializer;
+
+    const FORMAT: &str = "%D";
+
+    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = format!("{}", date.format(FORMAT));
+        serializer.serialize_str(&s)
+    }
+}
+
+pub mod optional_date_format {
+    use chrono::{DateTime, Utc};
+    use rocket::serde::Serializer;
+
+    pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match date {
+            Some(date) => super::date_format::serialize(date, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Dates with times, for when the day alone isn't enough
+pub mod datetime_format {
+    use chrono::{DateTime, Utc};
+    use rocket::serde::Serializer;
+
+    const FORMAT: &str = "%F %R UTC";
+
+    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+}
+
+/// Dates with times in the format taken by `datetime-local` inputs
+pub mod optional_datetime_format {
+    use chrono::{DateTime, Utc};
+    use rocket::serde::Serializer;
+
+    pub const FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+    pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match date {
+            Some(date) => serializer.serialize_str(&date.format(FORMAT).to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+}
@@ -18,13 +18,17 @@
 use chrono::{DateTime, Utc};
 use rocket::serde::Serialize;
 use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::pg::Pg;
 use rocket_sync_db_pools::diesel::prelude::*;
 
 use crate::db::DbConn;
+use crate::models::aliases::Alias;
+use crate::models::formatters::{date_format, optional_datetime_format};
 use crate::models::users::User;
-use crate::schema::links;
+use crate::schema::{aliases, link_tags, links, tags};
+use crate::url_template;
 
-#[derive(Queryable, Insertable, Serialize, Associations)]
+#[derive(Queryable, Insertable, Serialize, Associations, Clone)]
 #[serde(crate = "rocket::serde")]
 #[belongs_to(User, foreign_key = "created_by")]
 #[table_name = "links"]
@@ -35,14 +39,352 @@ pub struct Link {
     #[serde(with = "date_format")]
     pub created_at: DateTime<Utc>,
     pub created_by: i32,
+    #[serde(with = "optional_datetime_format")]
+    pub active_from: Option<DateTime<Utc>>,
+    #[serde(with = "optional_datetime_format")]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub fallback_url: Option<String>,
+    pub redirect_code: i16,
+    /// Passes the rest of the path and the query on to the long
+    pub prefix: bool,
+    #[serde(skip_serializing)]
+    pub pw_hash: Option<String>,
+    /// Only redirects for users that are logged in
+    pub internal: bool,
+    /// How many times the link can be followed, or `None` for no limit
+    pub max_clicks: Option<i32>,
+    pub clicks_left: Option<i32>,
+    /// Redirects straight away even when the long isn't on a trusted domain
+    pub skip_warning: bool,
+}
+
+/// The columns editing a link saves. Who made it and how many clicks it has left are left out,
+/// so saving an edit can't undo clicks taken since the link was loaded
+#[derive(AsChangeset)]
+#[table_name = "links"]
+#[changeset_options(treat_none_as_null = "true")]
+struct LinkChanges<'a> {
+    long: &'a str,
+    notes: &'a str,
+    active_from: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    fallback_url: Option<&'a str>,
+    redirect_code: i16,
+    prefix: bool,
+    pw_hash: Option<&'a str>,
+    internal: bool,
+    max_clicks: Option<i32>,
+    skip_warning: bool,
+}
+
+impl<'a> LinkChanges<'a> {
+    fn new(link: &'a Link) -> LinkChanges<'a> {
+        LinkChanges {
+            long: &link.long,
+            notes: &link.notes,
+            active_from: link.active_from,
+            expires_at: link.expires_at,
+            fallback_url: link.fallback_url.as_deref(),
+            redirect_code: link.redirect_code,
+            prefix: link.prefix,
+            pw_hash: link.pw_hash.as_deref(),
+            internal: link.internal,
+            max_clicks: link.max_clicks,
+            skip_warning: link.skip_warning,
+        }
+    }
+}
+
+/// The http statuses a link can redirect with
+pub const REDIRECT_CODES: [i16; 4] = [301, 302, 307, 308];
+
+/// When a link redirects and where it goes when it doesn't
+#[derive(AsChangeset)]
+#[table_name = "links"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct LinkSchedule {
+    pub active_from: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub fallback_url: Option<String>,
+}
+
+#[derive(PartialEq)]
+pub enum LinkStatus {
+    Active,
+    Scheduled,
+    Expired,
+    Exhausted,
+}
+
+impl LinkStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkStatus::Active => "Active",
+            LinkStatus::Scheduled => "Scheduled",
+            LinkStatus::Expired => "Expired",
+            LinkStatus::Exhausted => "Exhausted",
+        }
+    }
+}
+
+/// The columns the link tables can be sorted by
+#[derive(FromFormField, Clone, Copy, PartialEq)]
+pub enum LinkSort {
+    #[field(value = "short")]
+    Short,
+    #[field(value = "long")]
+    Long,
+    #[field(value = "created")]
+    Created,
+}
+
+impl LinkSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkSort::Short => "short",
+            LinkSort::Long => "long",
+            LinkSort::Created => "created",
+        }
+    }
+
+    /// Text sorts alphabetically and dates newest first unless asked otherwise
+    pub fn default_direction(&self) -> SortDirection {
+        match self {
+            LinkSort::Created => SortDirection::Desc,
+            _ => SortDirection::Asc,
+        }
+    }
+}
+
+#[derive(FromFormField, Clone, Copy, PartialEq)]
+pub enum SortDirection {
+    #[field(value = "asc")]
+    Asc,
+    #[field(value = "desc")]
+    Desc,
+}
+
+impl SortDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        }
+    }
+}
+
+/// Which links to show in a table and in what order
+#[derive(Clone)]
+pub struct LinkQuery {
+    /// Only links created by this user
+    pub owner: Option<i32>,
+    /// Matched case insensitively against the short, long, notes and aliases
+    pub search: Option<String>,
+    pub tag: Option<String>,
+    pub sort: LinkSort,
+    pub direction: SortDirection,
+}
+
+impl LinkQuery {
+    /// Every link, newest first
+    pub fn new(owner: Option<i32>) -> LinkQuery {
+        LinkQuery {
+            owner,
+            search: None,
+            tag: None,
+            sort: LinkSort::Created,
+            direction: SortDirection::Desc,
+        }
+    }
+
+    fn filtered(&self) -> links::BoxedQuery<'static, Pg> {
+        let mut query = links::table.into_boxed();
+        if let Some(user_id) = self.owner {
+            query = query.filter(links::created_by.eq(user_id));
+        }
+        if let Some(search) = &self.search {
+            let pattern = format!("%{}%", escape_like(search));
+            let aliased = aliases::table
+                .filter(aliases::alias.ilike(pattern.clone()))
+                .select(aliases::short);
+            query = query.filter(
+                links::short
+                    .ilike(pattern.clone())
+                    .or(links::long.ilike(pattern.clone()))
+                    .or(links::notes.ilike(pattern))
+                    .or(links::short.eq_any(aliased)),
+            );
+        }
+        if let Some(tag) = &self.tag {
+            let tagged = link_tags::table
+                .inner_join(tags::table)
+                .filter(tags::name.eq(tag.clone()))
+                .select(link_tags::short);
+            query = query.filter(links::short.eq_any(tagged));
+        }
+        query
+    }
+
+    fn sorted(&self) -> links::BoxedQuery<'static, Pg> {
+        let query = self.filtered();
+        // the short breaks ties so pages don't shift between requests
+        match (self.sort, self.direction) {
+            (LinkSort::Short, SortDirection::Asc) => query.order(links::short.asc()),
+            (LinkSort::Short, SortDirection::Desc) => query.order(links::short.desc()),
+            (LinkSort::Long, SortDirection::Asc) => {
+                query.order((links::long.asc(), links::short.asc()))
+            }
+            (LinkSort::Long, SortDirection::Desc) => {
+                query.order((links::long.desc(), links::short.asc()))
+            }
+            (LinkSort::Created, SortDirection::Asc) => {
+                query.order((links::created_at.asc(), links::short.asc()))
+            }
+            (LinkSort::Created, SortDirection::Desc) => {
+                query.order((links::created_at.desc(), links::short.asc()))
+            }
+        }
+    }
+}
+
+/// Escapes the wildcards in a string so it's matched literally by LIKE
+fn escape_like(search: &str) -> String {
+    search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
 
 impl Link {
+    pub fn new(
+        short: String,
+        long: String,
+        notes: String,
+        created_by: i32,
+        redirect_code: i16,
+    ) -> Link {
+        Link {
+            short,
+            long,
+            notes,
+            created_at: Utc::now(),
+            created_by,
+            active_from: None,
+            expires_at: None,
+            fallback_url: None,
+            redirect_code,
+            prefix: false,
+            pw_hash: None,
+            internal: false,
+            max_clicks: None,
+            clicks_left: None,
+            skip_warning: false,
+        }
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> LinkStatus {
+        if self.active_from.is_some_and(|from| now < from) {
+            LinkStatus::Scheduled
+        } else if self.expires_at.is_some_and(|until| now >= until) {
+            LinkStatus::Expired
+        } else if self.clicks_left.is_some_and(|left| left <= 0) {
+            LinkStatus::Exhausted
+        } else {
+            LinkStatus::Active
+        }
+    }
+
+    pub fn schedule(&self) -> LinkSchedule {
+        LinkSchedule {
+            active_from: self.active_from,
+            expires_at: self.expires_at,
+            fallback_url: self.fallback_url.clone(),
+        }
+    }
+
+    /// Templated longs take their placeholders from the path after the short
+    pub fn is_template(&self) -> bool {
+        url_template::has_placeholders(&self.long)
+    }
+
+    /// Where a visit redirects to. Prefix links append the rest of the path
+    /// and the query, which are expected to still be percent encoded.
+    pub fn destination(&self, rest: &str, query: Option<&str>) -> String {
+        if self.is_template() {
+            return url_template::fill(&self.long, rest, query);
+        }
+        if !self.prefix {
+            return self.long.clone();
+        }
+
+        // the path goes before the long's own query, and both go before its fragment
+        let (long, fragment) = match self.long.find('#') {
+            Some(index) => self.long.split_at(index),
+            None => (self.long.as_str(), ""),
+        };
+        let (path, long_query) = match long.find('?') {
+            Some(index) => (&long[..index], Some(&long[index + 1..])),
+            None => (long, None),
+        };
+
+        let mut url = path.to_string();
+        if !rest.is_empty() {
+            if !url.ends_with('/') {
+                url.push('/');
+            }
+            url.push_str(rest);
+        }
+        let queries: Vec<&str> = long_query
+            .into_iter()
+            .chain(query)
+            .filter(|query| !query.is_empty())
+            .collect();
+        if !queries.is_empty() {
+            url.push('?');
+            url.push_str(&queries.join("&"));
+        }
+        url.push_str(fragment);
+        url
+    }
+
+    /// Links that change over time or need a login or password shouldn't be cached by browsers
+    pub fn is_cacheable(&self) -> bool {
+        self.active_from.is_none()
+            && self.expires_at.is_none()
+            && self.pw_hash.is_none()
+            && !self.internal
+            && self.max_clicks.is_none()
+    }
+
+    /// Sets how many times the link can be followed, starting the count over
+    pub fn limit_clicks(&mut self, max_clicks: Option<i32>) {
+        self.max_clicks = max_clicks;
+        self.clicks_left = max_clicks;
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.pw_hash.is_some()
+    }
+
     pub async fn get(short: String, db: &DbConn) -> QueryResult<Link> {
         db.run(move |conn| links::table.find(short).get_result::<Link>(conn))
             .await
     }
 
+    /// Finds the link a short redirects with, following it if it's an alias
+    pub async fn resolve(short: String, db: &DbConn) -> QueryResult<Link> {
+        db.run(move |conn| {
+            let aliased = aliases::table
+                .filter(aliases::alias.eq(short.clone()))
+                .select(aliases::short);
+            links::table
+                .filter(links::short.eq(&short).or(links::short.eq_any(aliased)))
+                .order(links::short.eq(&short).desc())
+                .first::<Link>(conn)
+        })
+        .await
+    }
+
     pub async fn all(db: &DbConn) -> QueryResult<Vec<Link>> {
         db.run(move |conn| {
             links::table
@@ -63,6 +405,33 @@ impl Link {
         .await
     }
 
+    /// One page of the links matching a query
+    pub async fn page(
+        query: LinkQuery,
+        limit: i64,
+        offset: i64,
+        db: &DbConn,
+    ) -> QueryResult<Vec<Link>> {
+        db.run(move |conn| {
+            query
+                .sorted()
+                .limit(limit)
+                .offset(offset)
+                .get_results::<Link>(conn)
+        })
+        .await
+    }
+
+    /// How many links match a query across every page
+    pub async fn count_matching(query: LinkQuery, db: &DbConn) -> QueryResult<i64> {
+        db.run(move |conn| query.filtered().count().get_result(conn))
+            .await
+    }
+
+    pub async fn count(owner: Option<i32>, db: &DbConn) -> QueryResult<i64> {
+        Link::count_matching(LinkQuery::new(owner), db).await
+    }
+
     pub async fn update(short: String, new_long: String, db: &DbConn) -> QueryResult<Link> {
         use crate::schema::links::dsl::long;
         db.run(move |conn| {
@@ -73,11 +442,100 @@ impl Link {
         .await
     }
 
-    pub async fn insert(link: Link, db: &DbConn) -> QueryResult<usize> {
+    /// Saves the edits made to the link stored under `short`. Its clicks left are only
+    /// written when its limit changed, which starts the count over.
+    /// When the short changes, `keep_alias` leaves the old one redirecting to the link.
+    pub async fn save(
+        short: String,
+        link: Link,
+        keep_alias: bool,
+        db: &DbConn,
+    ) -> QueryResult<Link> {
+        db.run(move |conn| {
+            conn.transaction(|| {
+                // locked so clicks can't be taken between checking the limit and saving
+                let current: Link = links::table.find(&short).for_update().get_result(conn)?;
+
+                let renamed = link.short != short;
+                if renamed {
+                    // taking back one of its own aliases frees it up first
+                    diesel::delete(
+                        aliases::table
+                            .filter(aliases::alias.eq(&link.short))
+                            .filter(aliases::short.eq(&short)),
+                    )
+                    .execute(conn)?;
+
+                    // the changeset leaves out the primary key, so it's renamed on its own
+                    diesel::update(links::table.find(&short))
+                        .set(links::short.eq(&link.short))
+                        .execute(conn)?;
+                }
+
+                let mut saved = diesel::update(links::table.find(&link.short))
+                    .set(LinkChanges::new(&link))
+                    .get_result::<Link>(conn)?;
+                if link.max_clicks != current.max_clicks {
+                    saved = diesel::update(links::table.find(&link.short))
+                        .set(links::clicks_left.eq(link.clicks_left))
+                        .get_result::<Link>(conn)?;
+                }
+
+                if renamed && keep_alias {
+                    diesel::insert_into(aliases::table)
+                        .values(Alias::new(short, saved.short.clone()))
+                        .execute(conn)?;
+                }
+                Ok(saved)
+            })
+        })
+        .await
+    }
+
+    /// Takes one of a limited link's clicks, in a single update so concurrent
+    /// visits can't both take the last one. False when none are left.
+    pub async fn use_click(short: String, db: &DbConn) -> QueryResult<bool> {
+        db.run(move |conn| {
+            diesel::update(links::table.find(short).filter(links::clicks_left.gt(0)))
+                .set(links::clicks_left.eq(links::clicks_left - 1))
+                .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    pub async fn update_redirect_code(
+        short: String,
+        new_code: i16,
+        db: &DbConn,
+    ) -> QueryResult<Link> {
+        db.run(move |conn| {
+            diesel::update(links::table.find(short))
+                .set(links::redirect_code.eq(new_code))
+                .get_result::<Link>(conn)
+        })
+        .await
+    }
+
+    pub async fn update_schedule(
+        short: String,
+        schedule: LinkSchedule,
+        db: &DbConn,
+    ) -> QueryResult<Link> {
+        db.run(move |conn| {
+            diesel::update(links::table.find(short))
+                .set(&schedule)
+                .get_result::<Link>(conn)
+        })
+        .await
+    }
+
+    pub async fn insert(link: Link, db: &DbConn) -> QueryResult<Link> {
+        // the database keeps shorts from being taken by aliases
         db.run(move |conn| {
             diesel::insert_into(links::table)
                 .values(&link)
-                .execute(conn)
+                .get_result::<Link>(conn)
         })
         .await
     }
@@ -91,20 +549,3 @@ impl Link {
             .await
     }
 }
-
-/* ------------------------------- formatters ------------------------------- */
-
-mod date_format {
-    use chrono::{DateTime, Utc};
-    use rocket::serde::Serializer;
-
-    const FORMAT: &'static str = "%D";
-
-    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let s = format!("{}", date.format(FORMAT));
-        serializer.serialize_str(&s)
-    }
-}
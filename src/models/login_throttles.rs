@@ -0,0 +1,252 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+
+use crate::db::DbConn;
+use crate::models::formatters::datetime_format;
+use crate::schema::login_throttles;
+
+/// Failures before each one starts adding a wait
+const FREE_FAILURES: i32 = 3;
+
+/// How long failures are remembered after the last one
+const FORGET_AFTER_HOURS: i64 = 24;
+
+/// What the failed logins of a throttle were counted against
+#[derive(Clone, Copy)]
+pub enum ThrottleKind {
+    Username,
+    Ip,
+    /// guesses at a link's password from one ip
+    Link,
+}
+
+impl ThrottleKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ThrottleKind::Username => "username",
+            ThrottleKind::Ip => "ip",
+            ThrottleKind::Link => "link",
+        }
+    }
+}
+
+/// How failed logins slow things down: the wait doubles with each failure after the first
+/// few, until `max_failures` locks it out for the whole `lockout`
+#[derive(Clone, Copy)]
+pub struct Backoff {
+    pub max_failures: i32,
+    pub lockout: Duration,
+}
+
+impl Backoff {
+    fn wait(self, failures: i32) -> Option<Duration> {
+        if failures >= self.max_failures {
+            Some(self.lockout)
+        } else if failures >= FREE_FAILURES {
+            let seconds = 1i64 << (failures - FREE_FAILURES).min(20);
+            Some(Duration::seconds(seconds).min(self.lockout))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct LoginThrottle {
+    pub id: i32,
+    pub kind: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// A throttle that's currently keeping logins out, for admins to look over
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Lockout {
+    pub id: i32,
+    pub kind: String,
+    pub subject: String,
+    pub failures: i32,
+    #[serde(with = "datetime_format")]
+    pub locked_until: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_throttles"]
+struct NewThrottle {
+    kind: &'static str,
+    subject: String,
+    last_failure_at: DateTime<Utc>,
+}
+
+impl LoginThrottle {
+    /// Counts an attempt as failed against each subject before it's checked, so parallel
+    /// attempts can't all get in before the first failure is counted. When any of them is
+    /// locked out nothing is counted, and the latest time they're locked until is given instead
+    pub async fn reserve(
+        subjects: Vec<(ThrottleKind, String, Backoff)>,
+        db: &DbConn,
+    ) -> QueryResult<Option<DateTime<Utc>>> {
+        db.run(move |conn| {
+            conn.transaction(|| {
+                let now = Utc::now();
+
+                // anything that's been forgotten doesn't need to be kept around
+                diesel::delete(
+                    login_throttles::table
+                        .filter(
+                            login_throttles::last_failure_at
+                                .lt(now - Duration::hours(FORGET_AFTER_HOURS)),
+                        )
+                        .filter(
+                            login_throttles::locked_until
+                                .is_null()
+                                .or(login_throttles::locked_until.lt(now)),
+                        ),
+                )
+                .execute(conn)?;
+
+                let mut throttles = vec![];
+                for (kind, subject, backoff) in subjects {
+                    diesel::insert_into(login_throttles::table)
+                        .values(NewThrottle {
+                            kind: kind.as_str(),
+                            subject: subject.clone(),
+                            last_failure_at: now,
+                        })
+                        .on_conflict_do_nothing()
+                        .execute(conn)?;
+
+                    // locked so parallel attempts wait their turn instead of counting from
+                    // the same number
+                    let throttle: LoginThrottle = login_throttles::table
+                        .filter(login_throttles::kind.eq(kind.as_str()))
+                        .filter(login_throttles::subject.eq(subject))
+                        .for_update()
+                        .get_result(conn)?;
+                    throttles.push((throttle, backoff));
+                }
+
+                let locked_until = throttles
+                    .iter()
+                    .filter_map(|(throttle, _)| throttle.locked_until)
+                    .filter(|locked_until| *locked_until > now)
+                    .max();
+                if locked_until.is_some() {
+                    return Ok(locked_until);
+                }
+
+                for (throttle, backoff) in throttles {
+                    let forgotten =
+                        throttle.last_failure_at < now - Duration::hours(FORGET_AFTER_HOURS);
+                    let failures = if forgotten { 1 } else { throttle.failures + 1 };
+                    diesel::update(login_throttles::table.find(throttle.id))
+                        .set((
+                            login_throttles::failures.eq(failures),
+                            login_throttles::last_failure_at.eq(now),
+                            login_throttles::locked_until
+                                .eq(backoff.wait(failures).map(|wait| now + wait)),
+                        ))
+                        .execute(conn)?;
+                }
+                Ok(None)
+            })
+        })
+        .await
+    }
+
+    /// Gives back one reserved attempt, once it turned out not to be a wrong guess
+    pub async fn release(
+        kind: ThrottleKind,
+        subject: String,
+        backoff: Backoff,
+        db: &DbConn,
+    ) -> QueryResult<()> {
+        db.run(move |conn| {
+            conn.transaction(|| {
+                let throttle: Option<LoginThrottle> = login_throttles::table
+                    .filter(login_throttles::kind.eq(kind.as_str()))
+                    .filter(login_throttles::subject.eq(subject))
+                    .for_update()
+                    .get_result(conn)
+                    .optional()?;
+                if let Some(throttle) = throttle {
+                    let failures = (throttle.failures - 1).max(0);
+                    let locked_until = backoff
+                        .wait(failures)
+                        .map(|wait| throttle.last_failure_at + wait);
+                    diesel::update(login_throttles::table.find(throttle.id))
+                        .set((
+                            login_throttles::failures.eq(failures),
+                            login_throttles::locked_until.eq(locked_until),
+                        ))
+                        .execute(conn)?;
+                }
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Forgets the failures of a subject, like once it logs in successfully
+    pub async fn clear(kind: ThrottleKind, subject: String, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::delete(
+                login_throttles::table
+                    .filter(login_throttles::kind.eq(kind.as_str()))
+                    .filter(login_throttles::subject.eq(subject)),
+            )
+            .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn clear_by_id(id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| diesel::delete(login_throttles::table.find(id)).execute(conn))
+            .await
+    }
+
+    pub async fn all_locked(db: &DbConn) -> QueryResult<Vec<Lockout>> {
+        let throttles: Vec<LoginThrottle> = db
+            .run(move |conn| {
+                login_throttles::table
+                    .filter(login_throttles::locked_until.gt(Utc::now()))
+                    .order(login_throttles::locked_until.desc())
+                    .load(conn)
+            })
+            .await?;
+        Ok(throttles
+            .into_iter()
+            .filter_map(|throttle| {
+                Some(Lockout {
+                    locked_until: throttle.locked_until?,
+                    id: throttle.id,
+                    kind: throttle.kind,
+                    subject: throttle.subject,
+                    failures: throttle.failures,
+                })
+            })
+            .collect())
+    }
+}
@@ -15,5 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
+pub mod aliases;
+pub mod clicks;
+pub mod formatters;
 pub mod links;
+pub mod login_throttles;
+pub mod sessions;
+pub mod settings;
+pub mod tags;
+pub mod tokens;
+pub mod two_factor;
 pub mod users;
@@ -0,0 +1,167 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+
+use crate::db::DbConn;
+use crate::models::formatters::datetime_format;
+use crate::models::users::User;
+use crate::schema::sessions;
+
+/// A browser that's logged in, found by the hash of the token in its cookie
+#[derive(Queryable, Serialize, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "sessions"]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    #[serde(with = "datetime_format")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "datetime_format")]
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Everything but the hash, which is only needed to look a session up
+type Columns = (
+    sessions::id,
+    sessions::user_id,
+    sessions::created_at,
+    sessions::last_seen_at,
+    sessions::ip,
+    sessions::user_agent,
+);
+const COLUMNS: Columns = (
+    sessions::id,
+    sessions::user_id,
+    sessions::created_at,
+    sessions::last_seen_at,
+    sessions::ip,
+    sessions::user_agent,
+);
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// When sessions stop working: after going unused for a while, or after a while no matter what
+#[derive(Clone, Copy)]
+pub struct Expiry {
+    pub idle_since: DateTime<Utc>,
+    pub created_since: DateTime<Utc>,
+}
+
+impl Session {
+    pub async fn insert(session: NewSession, db: &DbConn) -> QueryResult<Session> {
+        db.run(move |conn| {
+            diesel::insert_into(sessions::table)
+                .values(session)
+                .returning(COLUMNS)
+                .get_result(conn)
+        })
+        .await
+    }
+
+    /// The session with a token hash, if it hasn't expired
+    pub async fn get_active(hash: String, expiry: Expiry, db: &DbConn) -> QueryResult<Session> {
+        db.run(move |conn| {
+            sessions::table
+                .select(COLUMNS)
+                .filter(sessions::token_hash.eq(hash))
+                .filter(sessions::last_seen_at.gt(expiry.idle_since))
+                .filter(sessions::created_at.gt(expiry.created_since))
+                .get_result(conn)
+        })
+        .await
+    }
+
+    pub async fn all_for_user(user_id: i32, db: &DbConn) -> QueryResult<Vec<Session>> {
+        db.run(move |conn| {
+            sessions::table
+                .select(COLUMNS)
+                .filter(sessions::user_id.eq(user_id))
+                .order(sessions::last_seen_at.desc())
+                .load(conn)
+        })
+        .await
+    }
+
+    pub async fn touch(id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::update(sessions::table.find(id))
+                .set(sessions::last_seen_at.eq(Utc::now()))
+                .execute(conn)
+        })
+        .await
+    }
+
+    /// Logs out a session, but only if it belongs to the given user
+    pub async fn revoke(id: i32, user_id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::delete(
+                sessions::table
+                    .find(id)
+                    .filter(sessions::user_id.eq(user_id)),
+            )
+            .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn revoke_by_hash(hash: String, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::delete(sessions::table.filter(sessions::token_hash.eq(hash))).execute(conn)
+        })
+        .await
+    }
+
+    /// Logs a user out everywhere
+    pub async fn revoke_all(user_id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| revoke_all_for_user(user_id, conn)).await
+    }
+
+    pub async fn delete_expired(expiry: Expiry, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::delete(
+                sessions::table.filter(
+                    sessions::last_seen_at
+                        .le(expiry.idle_since)
+                        .or(sessions::created_at.le(expiry.created_since)),
+                ),
+            )
+            .execute(conn)
+        })
+        .await
+    }
+}
+
+/// For the changes to a user that have to log them out everywhere in the same transaction
+pub fn revoke_all_for_user(user_id: i32, conn: &PgConnection) -> QueryResult<usize> {
+    diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(conn)
+}
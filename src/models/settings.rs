@@ -0,0 +1,54 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+
+use crate::db::DbConn;
+use crate::models::users::User;
+use crate::schema::settings;
+
+/// Settings for the whole instance that admins can change while it's running
+#[derive(Queryable)]
+pub struct Settings {
+    pub require_two_factor: bool,
+}
+
+impl Settings {
+    pub async fn get(db: &DbConn) -> QueryResult<Settings> {
+        db.run(move |conn| {
+            settings::table
+                .select((settings::require_two_factor,))
+                .first(conn)
+        })
+        .await
+    }
+
+    pub async fn update_require_two_factor(require: bool, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::update(settings::table)
+                .set(settings::require_two_factor.eq(require))
+                .execute(conn)
+        })
+        .await
+    }
+
+    /// Whether a user has to set up two factor before they can log in
+    pub fn requires_two_factor(&self, user: &User) -> bool {
+        self.require_two_factor && (user.manage_links || user.manage_users)
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+use std::collections::HashMap;
+
+use crate::db::DbConn;
+use crate::schema::{link_tags, links, tags};
+
+#[derive(Queryable, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "tags"]
+struct NewTag {
+    name: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "link_tags"]
+struct NewLinkTag {
+    short: String,
+    tag_id: i32,
+}
+
+impl Tag {
+    pub async fn all(db: &DbConn) -> QueryResult<Vec<Tag>> {
+        db.run(move |conn| tags::table.order(tags::name).load(conn))
+            .await
+    }
+
+    /// Tags that are on at least one of the user's links
+    pub async fn all_for_user(user_id: i32, db: &DbConn) -> QueryResult<Vec<Tag>> {
+        db.run(move |conn| {
+            tags::table
+                .filter(
+                    tags::id.eq_any(
+                        link_tags::table
+                            .inner_join(links::table)
+                            .filter(links::created_by.eq(user_id))
+                            .select(link_tags::tag_id),
+                    ),
+                )
+                .order(tags::name)
+                .load(conn)
+        })
+        .await
+    }
+
+    pub async fn names_for_link(short: String, db: &DbConn) -> QueryResult<Vec<String>> {
+        db.run(move |conn| {
+            link_tags::table
+                .inner_join(tags::table)
+                .filter(link_tags::short.eq(short))
+                .select(tags::name)
+                .order(tags::name)
+                .load(conn)
+        })
+        .await
+    }
+
+    /// The names of the tags on each of the links, keyed by short
+    pub async fn names_for_links(
+        shorts: Vec<String>,
+        db: &DbConn,
+    ) -> QueryResult<HashMap<String, Vec<String>>> {
+        let pairs: Vec<(String, String)> = db
+            .run(move |conn| {
+                link_tags::table
+                    .inner_join(tags::table)
+                    .filter(link_tags::short.eq_any(shorts))
+                    .select((link_tags::short, tags::name))
+                    .order(tags::name)
+                    .load(conn)
+            })
+            .await?;
+
+        let mut names: HashMap<String, Vec<String>> = HashMap::new();
+        for (short, name) in pairs {
+            names.entry(short).or_default().push(name);
+        }
+        Ok(names)
+    }
+
+    /// Replaces the tags on a link, creating any that don't exist yet
+    /// and removing ones that are no longer on any link
+    pub async fn set_for_link(short: String, names: Vec<String>, db: &DbConn) -> QueryResult<()> {
+        db.run(move |conn| {
+            conn.transaction(|| {
+                let new_tags: Vec<NewTag> = names
+                    .iter()
+                    .map(|name| NewTag { name: name.clone() })
+                    .collect();
+                diesel::insert_into(tags::table)
+                    .values(&new_tags)
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                let ids: Vec<i32> = tags::table
+                    .filter(tags::name.eq_any(&names))
+                    .select(tags::id)
+                    .load(conn)?;
+
+                diesel::delete(link_tags::table.filter(link_tags::short.eq(&short)))
+                    .execute(conn)?;
+                let new_link_tags: Vec<NewLinkTag> = ids
+                    .into_iter()
+                    .map(|tag_id| NewLinkTag {
+                        short: short.clone(),
+                        tag_id,
+                    })
+                    .collect();
+                diesel::insert_into(link_tags::table)
+                    .values(&new_link_tags)
+                    .execute(conn)?;
+
+                diesel::delete(tags::table.filter(diesel::dsl::not(
+                    tags::id.eq_any(link_tags::table.select(link_tags::tag_id)),
+                )))
+                .execute(conn)?;
+                Ok(())
+            })
+        })
+        .await
+    }
+}
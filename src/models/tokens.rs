@@ -0,0 +1,170 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Method;
+use rocket::serde::Serialize;
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+
+use crate::db::DbConn;
+use crate::models::formatters::{date_format, optional_date_format};
+use crate::models::users::User;
+use crate::schema::api_tokens;
+
+#[derive(Queryable, Serialize, Associations)]
+#[serde(crate = "rocket::serde")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "api_tokens"]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub scope: String,
+    #[serde(with = "date_format")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "optional_date_format")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Everything but the hash, which is never needed after a token is looked up
+type Columns = (
+    api_tokens::id,
+    api_tokens::user_id,
+    api_tokens::name,
+    api_tokens::scope,
+    api_tokens::created_at,
+    api_tokens::last_used_at,
+);
+const COLUMNS: Columns = (
+    api_tokens::id,
+    api_tokens::user_id,
+    api_tokens::name,
+    api_tokens::scope,
+    api_tokens::created_at,
+    api_tokens::last_used_at,
+);
+
+#[derive(Insertable)]
+#[table_name = "api_tokens"]
+pub struct NewApiToken {
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scope: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a token is allowed to be used for
+#[derive(FromFormField, Clone, Copy, PartialEq)]
+pub enum TokenScope {
+    #[field(value = "full")]
+    Full,
+    #[field(value = "read_only")]
+    ReadOnly,
+    #[field(value = "links_only")]
+    LinksOnly,
+}
+
+impl TokenScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenScope::Full => "full",
+            TokenScope::ReadOnly => "read_only",
+            TokenScope::LinksOnly => "links_only",
+        }
+    }
+
+    pub fn from_str(scope: &str) -> Option<TokenScope> {
+        match scope {
+            "full" => Some(TokenScope::Full),
+            "read_only" => Some(TokenScope::ReadOnly),
+            "links_only" => Some(TokenScope::LinksOnly),
+            _ => None,
+        }
+    }
+
+    /// Whether a request with this method and path can be made with the scope
+    pub fn allows(self, method: Method, path: &str) -> bool {
+        match self {
+            TokenScope::Full => true,
+            TokenScope::ReadOnly => matches!(method, Method::Get | Method::Head | Method::Options),
+            TokenScope::LinksOnly => {
+                path.starts_with("/api/v1/links") || path.starts_with("/api/links/")
+            }
+        }
+    }
+}
+
+impl ApiToken {
+    pub fn scope(&self) -> Option<TokenScope> {
+        TokenScope::from_str(&self.scope)
+    }
+
+    pub async fn get_by_hash(hash: String, db: &DbConn) -> QueryResult<ApiToken> {
+        db.run(move |conn| {
+            api_tokens::table
+                .select(COLUMNS)
+                .filter(api_tokens::token_hash.eq(hash))
+                .get_result(conn)
+        })
+        .await
+    }
+
+    pub async fn all_for_user(user_id: i32, db: &DbConn) -> QueryResult<Vec<ApiToken>> {
+        db.run(move |conn| {
+            api_tokens::table
+                .select(COLUMNS)
+                .filter(api_tokens::user_id.eq(user_id))
+                .order(api_tokens::created_at.desc())
+                .load(conn)
+        })
+        .await
+    }
+
+    pub async fn insert(token: NewApiToken, db: &DbConn) -> QueryResult<ApiToken> {
+        db.run(move |conn| {
+            diesel::insert_into(api_tokens::table)
+                .values(token)
+                .returning(COLUMNS)
+                .get_result(conn)
+        })
+        .await
+    }
+
+    pub async fn touch(id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::update(api_tokens::table.find(id))
+                .set(api_tokens::last_used_at.eq(Utc::now()))
+                .execute(conn)
+        })
+        .await
+    }
+
+    /// Deletes a token, but only if it belongs to the given user
+    pub async fn revoke(id: i32, user_id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            diesel::delete(
+                api_tokens::table
+                    .find(id)
+                    .filter(api_tokens::user_id.eq(user_id)),
+            )
+            .execute(conn)
+        })
+        .await
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use rocket_sync_db_pools::diesel;
+use rocket_sync_db_pools::diesel::prelude::*;
+
+use crate::db::DbConn;
+use crate::schema::{recovery_codes, two_factor};
+
+/// A user's TOTP secret. Users with one have to enter a code after their password.
+#[derive(Queryable, Insertable)]
+#[table_name = "two_factor"]
+pub struct TwoFactor {
+    pub user_id: i32,
+    pub secret: String,
+    pub last_step: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+struct NewRecoveryCode {
+    user_id: i32,
+    code_hash: String,
+}
+
+impl TwoFactor {
+    pub fn new(user_id: i32, secret: String, step: i64) -> TwoFactor {
+        TwoFactor {
+            user_id,
+            secret,
+            last_step: step,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub async fn get(user_id: i32, db: &DbConn) -> QueryResult<Option<TwoFactor>> {
+        db.run(move |conn| two_factor::table.find(user_id).get_result(conn).optional())
+            .await
+    }
+
+    /// Turns on the second step for a user, replacing any recovery codes they had
+    pub async fn enroll(
+        two_factor: TwoFactor,
+        code_hashes: Vec<String>,
+        db: &DbConn,
+    ) -> QueryResult<()> {
+        db.run(move |conn| {
+            conn.transaction(|| {
+                let user_id = two_factor.user_id;
+                diesel::delete(two_factor::table.find(user_id)).execute(conn)?;
+                diesel::insert_into(two_factor::table)
+                    .values(two_factor)
+                    .execute(conn)?;
+                replace_recovery_codes(user_id, code_hashes, conn)
+            })
+        })
+        .await
+    }
+
+    pub async fn remove(user_id: i32, db: &DbConn) -> QueryResult<usize> {
+        db.run(move |conn| {
+            conn.transaction(|| {
+                diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                    .execute(conn)?;
+                diesel::delete(two_factor::table.find(user_id)).execute(conn)
+            })
+        })
+        .await
+    }
+
+    /// Records that a code from a time step was used, in a single update so the same code
+    /// can't get through twice. False when a code from that step or a later one was already used.
+    pub async fn use_step(user_id: i32, step: i64, db: &DbConn) -> QueryResult<bool> {
+        db.run(move |conn| {
+            diesel::update(
+                two_factor::table
+                    .find(user_id)
+                    .filter(two_factor::last_step.lt(step)),
+            )
+            .set(two_factor::last_step.eq(step))
+            .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    pub async fn set_recovery_codes(
+        user_id: i32,
+        code_hashes: Vec<String>,
+        db: &DbConn,
+    ) -> QueryResult<()> {
+        db.run(move |conn| conn.transaction(|| replace_recovery_codes(user_id, code_hashes, conn)))
+            .await
+    }
+
+    /// Uses up one of a user's recovery codes. False when it isn't one of theirs.
+    pub async fn use_recovery_code(
+        user_id: i32,
+        code_hash: String,
+        db: &DbConn,
+    ) -> QueryResult<bool> {
+        db.run(move |conn| {
+            diesel::delete(
+                recovery_codes::table
+                    .filter(recovery_codes::user_id.eq(user_id))
+                    .filter(recovery_codes::code_hash.eq(code_hash)),
+            )
+            .execute(conn)
+        })
+        .await
+        .map(|deleted| deleted == 1)
+    }
+
+    pub async fn recovery_codes_left(user_id: i32, db: &DbConn) -> QueryResult<i64> {
+        db.run(move |conn| {
+            recovery_codes::table
+                .filter(recovery_codes::user_id.eq(user_id))
+                .count()
+                .get_result(conn)
+        })
+        .await
+    }
+}
+
+fn replace_recovery_codes(
+    user_id: i32,
+    code_hashes: Vec<String>,
+    conn: &PgConnection,
+) -> QueryResult<()> {
+    let codes: Vec<NewRecoveryCode> = code_hashes
+        .into_iter()
+        .map(|code_hash| NewRecoveryCode { user_id, code_hash })
+        .collect();
+    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+        .execute(conn)?;
+    diesel::insert_into(recovery_codes::table)
+        .values(codes)
+        .execute(conn)?;
+    Ok(())
+}
@@ -18,8 +18,10 @@
 use rocket::serde::Serialize;
 use rocket_sync_db_pools::diesel;
 use rocket_sync_db_pools::diesel::prelude::*;
+use rocket_sync_db_pools::diesel::result::Error;
 
 use crate::db::DbConn;
+use crate::models::sessions::revoke_all_for_user;
 use crate::schema::users;
 
 #[derive(Queryable, Serialize, FromForm)]
@@ -32,6 +34,7 @@ pub struct User {
     pub manage_links: bool,
     pub manage_users: bool,
     pub disabled: bool,
+    pub oidc_subject: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -42,6 +45,7 @@ pub struct InsertableUser {
     pub orig: bool,
     pub manage_links: bool,
     pub manage_users: bool,
+    pub oidc_subject: Option<String>,
 }
 
 #[derive(AsChangeset)]
@@ -68,6 +72,15 @@ impl User {
         .await
     }
 
+    pub async fn get_by_oidc_subject(subject: String, db: &DbConn) -> QueryResult<User> {
+        db.run(move |conn| {
+            users::table
+                .filter(users::oidc_subject.eq(subject))
+                .get_result(conn)
+        })
+        .await
+    }
+
     pub async fn all(db: &DbConn) -> QueryResult<Vec<User>> {
         db.run(move |conn| users::table.order(users::id.desc()).load(conn))
             .await
@@ -88,12 +101,16 @@ impl User {
             .await
     }
 
+    /// Disables a user and logs them out everywhere
     pub async fn disable(id: i32, db: &DbConn) -> QueryResult<usize> {
         use crate::schema::users::dsl::disabled;
         db.run(move |conn| {
-            diesel::update(users::table.find(id))
-                .set(disabled.eq(true))
-                .execute(conn)
+            conn.transaction(|| {
+                revoke_all_for_user(id, conn)?;
+                diesel::update(users::table.find(id))
+                    .set(disabled.eq(true))
+                    .execute(conn)
+            })
         })
         .await
     }
@@ -125,6 +142,22 @@ impl User {
         .await
     }
 
+    /// Gives a user the permissions their groups map to, unless they're the original user, whose
+    /// permissions can't be changed
+    pub async fn sync_permissions(
+        &mut self,
+        (manage_links, manage_users): (bool, bool),
+        db: &DbConn,
+    ) -> QueryResult<()> {
+        if self.orig || (self.manage_links, self.manage_users) == (manage_links, manage_users) {
+            return Ok(());
+        }
+        User::update_permissions(self.id, manage_links, manage_users, db).await?;
+        self.manage_links = manage_links;
+        self.manage_users = manage_users;
+        Ok(())
+    }
+
     pub async fn update_username(id: i32, new_name: String, db: &DbConn) -> QueryResult<usize> {
         use crate::schema::users::dsl::username;
         db.run(move |conn| {
@@ -135,16 +168,40 @@ impl User {
         .await
     }
 
+    /// Changes a user's password and logs them out everywhere, so the old one can't be relied on
     pub async fn update_password(id: i32, new_pw_hash: String, db: &DbConn) -> QueryResult<usize> {
         use crate::schema::users::dsl::pw_hash;
         db.run(move |conn| {
-            diesel::update(users::table.find(id))
-                .set(pw_hash.eq(new_pw_hash))
-                .execute(conn)
+            conn.transaction(|| {
+                revoke_all_for_user(id, conn)?;
+                diesel::update(users::table.find(id))
+                    .set(pw_hash.eq(new_pw_hash))
+                    .execute(conn)
+            })
         })
         .await
     }
 
+    /// Ties a user to someone at the identity provider, so they're found by subject from then on.
+    /// `None` unties them.
+    pub async fn set_oidc_subject(
+        id: i32,
+        subject: Option<String>,
+        db: &DbConn,
+    ) -> QueryResult<usize> {
+        let updated = db
+            .run(move |conn| {
+                diesel::update(users::table.find(id))
+                    .set(users::oidc_subject.eq(subject))
+                    .execute(conn)
+            })
+            .await?;
+        if updated == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(updated)
+    }
+
     pub async fn count(db: &DbConn) -> QueryResult<i64> {
         db.run(move |conn| users::table.select(diesel::dsl::count_star()).first(conn))
             .await
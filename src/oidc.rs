@@ -0,0 +1,313 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand_core::{OsRng, RngCore};
+use reqwest::{Client, Url};
+use rocket::serde::json::serde_json::{Map, Value};
+use rocket::serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::LinkrConfig;
+
+/// How long to wait on the identity provider before giving up
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Seconds an id token's times can be off by, for clocks that don't quite agree
+const LEEWAY: u64 = 60;
+
+const UNREACHABLE: &str = "The identity provider could not be reached";
+const INVALID_TOKEN: &str = "The identity provider's response could not be verified";
+
+pub type OidcResult<T> = Result<T, &'static str>;
+
+/// The endpoints logging in uses, from the identity provider's discovery document
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Provider {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// What's remembered between sending someone to the identity provider and them coming back
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LoginRequest {
+    pub state: String,
+    pub nonce: String,
+    /// the pkce verifier, which proves the code is redeemed by the browser that asked for it
+    pub verifier: String,
+    pub next: Option<String>,
+    /// the logged in user connecting their account, when it isn't a login
+    #[serde(default)]
+    pub connect: Option<i32>,
+}
+
+/// Who the identity provider says logged in
+pub struct Identity {
+    pub subject: String,
+    pub username: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+/// A public key the identity provider signs id tokens with. rsa keys have `n` and `e`, and
+/// elliptic curve keys have `x` and `y`
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Jwk {
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+impl LoginRequest {
+    pub fn new(next: Option<String>, connect: Option<i32>) -> LoginRequest {
+        LoginRequest {
+            state: random_string(),
+            nonce: random_string(),
+            verifier: random_string(),
+            next,
+            connect,
+        }
+    }
+}
+
+/// 43 url safe characters, which is also the shortest a pkce verifier can be
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+fn client() -> OidcResult<Client> {
+    Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|_| UNREACHABLE)
+}
+
+/// Looks up the identity provider's endpoints from its discovery document
+pub async fn discover(issuer: &str) -> OidcResult<Provider> {
+    let issuer = issuer.trim_end_matches('/');
+    let url = format!("{}/.well-known/openid-configuration", issuer);
+    let provider: Provider = client()?
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| UNREACHABLE)?
+        .json()
+        .await
+        .map_err(|_| UNREACHABLE)?;
+
+    // otherwise tokens from some other issuer could be accepted
+    if provider.issuer.trim_end_matches('/') != issuer {
+        return Err("The identity provider's issuer doesn't match its address");
+    }
+    Ok(provider)
+}
+
+/// Where to send someone to log in with the identity provider
+pub fn authorization_url(
+    provider: &Provider,
+    config: &LinkrConfig,
+    redirect_uri: &str,
+    request: &LoginRequest,
+) -> OidcResult<String> {
+    let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(request.verifier.as_bytes()));
+    let params = [
+        ("response_type", "code"),
+        ("client_id", &config.oidc_client_id),
+        ("redirect_uri", redirect_uri),
+        ("scope", &config.oidc_scopes),
+        ("state", &request.state),
+        ("nonce", &request.nonce),
+        ("code_challenge", &challenge),
+        ("code_challenge_method", "S256"),
+    ];
+    Url::parse_with_params(&provider.authorization_endpoint, &params)
+        .map(String::from)
+        .map_err(|_| "The identity provider's login page address is invalid")
+}
+
+/// Trades the code the identity provider sent back for an id token
+pub async fn exchange_code(
+    provider: &Provider,
+    config: &LinkrConfig,
+    redirect_uri: &str,
+    code: &str,
+    verifier: &str,
+) -> OidcResult<String> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", &config.oidc_client_id),
+        ("code_verifier", verifier),
+    ];
+    if let Some(secret) = &config.oidc_client_secret {
+        form.push(("client_secret", secret));
+    }
+
+    let tokens: TokenResponse = client()?
+        .post(&provider.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|_| UNREACHABLE)?
+        .error_for_status()
+        .map_err(|_| "The identity provider didn't accept the login")?
+        .json()
+        .await
+        .map_err(|_| INVALID_TOKEN)?;
+    Ok(tokens.id_token)
+}
+
+/// Checks an id token was signed by the identity provider for linkr and this login, giving
+/// back its claims
+pub async fn verify_id_token(
+    provider: &Provider,
+    config: &LinkrConfig,
+    id_token: &str,
+    nonce: &str,
+) -> OidcResult<Map<String, Value>> {
+    let header = decode_header(id_token).map_err(|_| INVALID_TOKEN)?;
+    let jwks: Jwks = client()?
+        .get(&provider.jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| UNREACHABLE)?
+        .json()
+        .await
+        .map_err(|_| INVALID_TOKEN)?;
+    let jwk = jwks
+        .keys
+        .iter()
+        .find(|key| header.kid.is_none() || key.kid == header.kid)
+        .ok_or(INVALID_TOKEN)?;
+
+    // only the public key algorithms, since anyone with the client secret could sign the others
+    let ec_point: Vec<u8>;
+    let key = match (header.alg, jwk) {
+        (
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512,
+            Jwk {
+                n: Some(n),
+                e: Some(e),
+                ..
+            },
+        ) => DecodingKey::from_rsa_components(n, e),
+        (
+            Algorithm::ES256 | Algorithm::ES384,
+            Jwk {
+                x: Some(x),
+                y: Some(y),
+                ..
+            },
+        ) => {
+            ec_point = uncompressed_point(x, y).ok_or(INVALID_TOKEN)?;
+            DecodingKey::from_ec_der(&ec_point)
+        }
+        _ => return Err(INVALID_TOKEN),
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.leeway = LEEWAY;
+    validation.iss = Some(provider.issuer.clone());
+    validation.set_audience(&[&config.oidc_client_id]);
+    let claims = decode::<Map<String, Value>>(id_token, &key, &validation)
+        .map_err(|_| INVALID_TOKEN)?
+        .claims;
+
+    // makes sure the token was made for this login and isn't being replayed from another
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(INVALID_TOKEN);
+    }
+    Ok(claims)
+}
+
+/// An elliptic curve key's coordinates, in the form they're verified with
+fn uncompressed_point(x: &str, y: &str) -> Option<Vec<u8>> {
+    let mut point = vec![0x04];
+    point.extend(BASE64URL_NOPAD.decode(x.as_bytes()).ok()?);
+    point.extend(BASE64URL_NOPAD.decode(y.as_bytes()).ok()?);
+    Some(point)
+}
+
+/// Picks out who logged in from an id token's claims
+pub fn identity(claims: &Map<String, Value>, config: &LinkrConfig) -> OidcResult<Identity> {
+    let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(String::from);
+    let subject = claim("sub").ok_or(INVALID_TOKEN)?;
+    let username = claim(&config.oidc_username_claim)
+        .filter(|username| !username.is_empty())
+        .ok_or("The identity provider didn't give a username")?;
+
+    // some identity providers give a single group as a string instead of a list
+    let groups = match claims.get(&config.oidc_groups_claim) {
+        Some(Value::Array(groups)) => groups
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        Some(Value::String(group)) => vec![group.clone()],
+        _ => vec![],
+    };
+
+    Ok(Identity {
+        subject,
+        username,
+        groups,
+    })
+}
+
+/// The permissions someone's groups give them as `(manage_links, manage_users)`, unless
+/// permissions aren't mapped from groups
+pub fn permissions(identity: &Identity, config: &LinkrConfig) -> Option<(bool, bool)> {
+    if config.oidc_link_groups.is_empty() && config.oidc_user_groups.is_empty() {
+        return None;
+    }
+    let in_any = |groups: &[String]| identity.groups.iter().any(|group| groups.contains(group));
+    Some((
+        in_any(&config.oidc_link_groups),
+        in_any(&config.oidc_user_groups),
+    ))
+}
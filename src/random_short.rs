@@ -0,0 +1,86 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use rand_core::{OsRng, RngCore};
+
+use crate::config::LinkrConfig;
+
+const LOOKALIKES: &str = "0Oo1lI";
+
+/// Keep the keyspace at most this full so random guesses rarely collide
+const KEYSPACE_HEADROOM: u128 = 16;
+
+pub struct ShortGenerator {
+    alphabet: Vec<char>,
+    min_length: usize,
+}
+
+impl ShortGenerator {
+    pub fn new(config: &LinkrConfig) -> ShortGenerator {
+        // shorts have to be alphanumeric, so anything else in the alphabet is dropped
+        let mut alphabet: Vec<char> = config
+            .random_short_alphabet
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .filter(|c| !config.random_short_exclude_lookalikes || !LOOKALIKES.contains(*c))
+            .collect();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+
+        if alphabet.is_empty() {
+            alphabet = ('a'..='z')
+                .filter(|c| !config.random_short_exclude_lookalikes || !LOOKALIKES.contains(*c))
+                .collect();
+        }
+
+        ShortGenerator {
+            alphabet,
+            min_length: config.random_short_length.max(1),
+        }
+    }
+
+    /// The length to start at so the keyspace stays sparse given how many shorts and aliases exist
+    pub fn length_for(&self, existing: i64) -> usize {
+        let needed = (existing.max(0) as u128 + 1) * KEYSPACE_HEADROOM;
+        let base = self.alphabet.len() as u128;
+
+        let mut length = self.min_length;
+        while base
+            .checked_pow(length as u32)
+            .is_some_and(|size| size < needed)
+        {
+            length += 1;
+        }
+        length
+    }
+
+    pub fn generate(&self, length: usize) -> String {
+        (0..length).map(|_| self.random_char()).collect()
+    }
+
+    fn random_char(&self) -> char {
+        // reject values past the last full multiple of the alphabet length to avoid bias
+        let len = self.alphabet.len() as u32;
+        let zone = u32::MAX - (u32::MAX % len);
+        loop {
+            let value = OsRng.next_u32();
+            if value < zone {
+                return self.alphabet[(value % len) as usize];
+            }
+        }
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use diesel::result::Error;
+use rocket::http::Status;
+use rocket::response::status::{Created, NoContent};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+
+use super::{nullable, ApiError, ApiResult};
+use crate::config::LinkrConfig;
+use crate::crypto::encrypt_pw;
+use crate::db::DbConn;
+use crate::models::aliases::Alias;
+use crate::models::links::{Link, LinkQuery, LinkSort, SortDirection};
+use crate::models::tags::Tag;
+use crate::models::users::User;
+use crate::routes::links::{
+    check_can_edit, insert_with_random_short, normalize_tags, validate_long, validate_max_clicks,
+    validate_redirect_code, validate_schedule, validate_short, validate_tags,
+};
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LinkResponse {
+    short: String,
+    long: String,
+    notes: String,
+    created_at: DateTime<Utc>,
+    created_by: i32,
+    active_from: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    fallback_url: Option<String>,
+    redirect_code: i16,
+    prefix: bool,
+    internal: bool,
+    skip_warning: bool,
+    protected: bool,
+    max_clicks: Option<i32>,
+    clicks_left: Option<i32>,
+    status: &'static str,
+    tags: Vec<String>,
+}
+
+impl LinkResponse {
+    fn new(link: Link, tags: Vec<String>) -> LinkResponse {
+        LinkResponse {
+            status: link.status(Utc::now()).as_str(),
+            short: link.short,
+            long: link.long,
+            notes: link.notes,
+            created_at: link.created_at,
+            created_by: link.created_by,
+            active_from: link.active_from,
+            expires_at: link.expires_at,
+            fallback_url: link.fallback_url,
+            redirect_code: link.redirect_code,
+            prefix: link.prefix,
+            internal: link.internal,
+            skip_warning: link.skip_warning,
+            protected: link.pw_hash.is_some(),
+            max_clicks: link.max_clicks,
+            clicks_left: link.clicks_left,
+            tags,
+        }
+    }
+
+    async fn load(link: Link, conn: &DbConn) -> ApiResult<LinkResponse> {
+        let tags = Tag::names_for_link(link.short.clone(), conn).await?;
+        Ok(LinkResponse::new(link, tags))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LinkList {
+    links: Vec<LinkResponse>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+/* ---------------------------------- read ---------------------------------- */
+
+#[derive(FromForm)]
+pub struct ListParams {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    all: Option<bool>,
+    q: Option<String>,
+    tag: Option<String>,
+    sort: Option<LinkSort>,
+    dir: Option<SortDirection>,
+}
+
+/// Lists the user's own links, or every link when `all` is set by someone who can manage links.
+/// They can be searched with `q`, filtered to a `tag` and sorted by `sort` in either `dir`.
+#[get("/?<params..>")]
+pub async fn list(params: ListParams, user: User, conn: DbConn) -> ApiResult<Json<LinkList>> {
+    let page = params.page.unwrap_or(1);
+    let per_page = params.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page < 1 || !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(ApiError::new(
+            Status::BadRequest,
+            format!(
+                "page must be at least 1 and per_page must be between 1 and {}",
+                MAX_PER_PAGE
+            ),
+        ));
+    }
+    let offset = (page - 1)
+        .checked_mul(per_page)
+        .ok_or_else(|| ApiError::new(Status::BadRequest, "page is too large"))?;
+
+    let owner = if params.all.unwrap_or(false) {
+        if !user.manage_links {
+            return Err(ApiError::new(
+                Status::Forbidden,
+                "You do not have permission to view all links",
+            ));
+        }
+        None
+    } else {
+        Some(user.id)
+    };
+
+    let mut query = LinkQuery::new(owner);
+    query.search = params.q.filter(|q| !q.is_empty());
+    query.tag = params.tag;
+    if let Some(sort) = params.sort {
+        query.sort = sort;
+        query.direction = sort.default_direction();
+    }
+    if let Some(dir) = params.dir {
+        query.direction = dir;
+    }
+
+    let total = Link::count_matching(query.clone(), &conn).await?;
+    let links = Link::page(query, per_page, offset, &conn).await?;
+    let shorts = links.iter().map(|link| link.short.clone()).collect();
+    let mut tags = Tag::names_for_links(shorts, &conn).await?;
+
+    Ok(Json(LinkList {
+        links: links
+            .into_iter()
+            .map(|link| {
+                let link_tags = tags.remove(&link.short).unwrap_or_default();
+                LinkResponse::new(link, link_tags)
+            })
+            .collect(),
+        page,
+        per_page,
+        total,
+    }))
+}
+
+#[get("/<short>")]
+pub async fn get(short: String, user: User, conn: DbConn) -> ApiResult<Json<LinkResponse>> {
+    check_can_edit(&user, &short, &conn).await?;
+    let link = Link::get(short, &conn).await?;
+    Ok(Json(LinkResponse::load(link, &conn).await?))
+}
+
+/* --------------------------------- create --------------------------------- */
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewLinkJson {
+    #[serde(default)]
+    short: String,
+    long: String,
+    #[serde(default)]
+    notes: String,
+    active_from: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    fallback_url: Option<String>,
+    redirect_code: Option<i16>,
+    #[serde(default)]
+    prefix: bool,
+    #[serde(default)]
+    internal: bool,
+    #[serde(default)]
+    skip_warning: bool,
+    password: Option<String>,
+    max_clicks: Option<i32>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[post("/", data = "<new_link>")]
+pub async fn create(
+    new_link: Json<NewLinkJson>,
+    user: User,
+    conn: DbConn,
+    config: &State<LinkrConfig>,
+) -> ApiResult<Created<Json<LinkResponse>>> {
+    let new_link = new_link.into_inner();
+
+    // a missing or blank short gets a random one generated
+    let random_short = new_link.short.is_empty();
+    let valid = if random_short {
+        validate_long(&new_link.long)
+    } else {
+        validate_short(&new_link.short).and(validate_long(&new_link.long))
+    };
+    valid.map_err(unprocessable)?;
+
+    let redirect_code = new_link
+        .redirect_code
+        .unwrap_or(config.default_redirect_code);
+    let mut link = Link::new(
+        new_link.short,
+        new_link.long,
+        new_link.notes,
+        user.id,
+        redirect_code,
+    );
+    link.active_from = new_link.active_from;
+    link.expires_at = new_link.expires_at;
+    link.fallback_url = new_link.fallback_url;
+    link.prefix = new_link.prefix;
+    link.internal = new_link.internal;
+    link.skip_warning = new_link.skip_warning;
+    validate_password(new_link.password.as_deref()).map_err(unprocessable)?;
+    link.pw_hash = new_link.password.as_deref().map(encrypt_pw);
+    validate_max_clicks(new_link.max_clicks).map_err(unprocessable)?;
+    link.limit_clicks(new_link.max_clicks);
+    validate_schedule(&link.schedule()).map_err(unprocessable)?;
+    validate_redirect_code(link.redirect_code).map_err(unprocessable)?;
+    let tags = normalize_tags(new_link.tags);
+    validate_tags(&tags).map_err(unprocessable)?;
+
+    let link = if random_short {
+        insert_with_random_short(link, config, &conn).await?
+    } else {
+        Link::insert(link, &conn).await?
+    };
+
+    Tag::set_for_link(link.short.clone(), tags.clone(), &conn).await?;
+
+    let location = format!("/api/v1/links/{}", link.short);
+    let response = LinkResponse::new(link, tags);
+
+    Ok(Created::new(location).body(Json(response)))
+}
+
+/* --------------------------------- update --------------------------------- */
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LinkPatch {
+    short: Option<String>,
+    /// Leaves the old short redirecting to the link when `short` changes it
+    #[serde(default)]
+    keep_alias: bool,
+    long: Option<String>,
+    notes: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    active_from: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    fallback_url: Option<Option<String>>,
+    redirect_code: Option<i16>,
+    prefix: Option<bool>,
+    internal: Option<bool>,
+    skip_warning: Option<bool>,
+    /// A new password, or null to remove it
+    #[serde(default, deserialize_with = "nullable")]
+    password: Option<Option<String>>,
+    /// A new click limit, starting the count over, or null to remove it
+    #[serde(default, deserialize_with = "nullable")]
+    max_clicks: Option<Option<i32>>,
+    tags: Option<Vec<String>>,
+}
+
+#[patch("/<short>", data = "<patch>")]
+pub async fn update(
+    short: String,
+    patch: Json<LinkPatch>,
+    user: User,
+    conn: DbConn,
+) -> ApiResult<Json<LinkResponse>> {
+    check_can_edit(&user, &short, &conn).await?;
+    let patch = patch.into_inner();
+
+    // apply the fields that were sent over the current link
+    let mut link = Link::get(short.clone(), &conn).await?;
+    if let Some(new_short) = patch.short.filter(|new_short| *new_short != short) {
+        validate_short(&new_short).map_err(unprocessable)?;
+        link.short = new_short;
+    }
+    if let Some(long) = patch.long {
+        validate_long(&long).map_err(unprocessable)?;
+        link.long = long;
+    }
+    if let Some(notes) = patch.notes {
+        link.notes = notes;
+    }
+    if let Some(active_from) = patch.active_from {
+        link.active_from = active_from;
+    }
+    if let Some(expires_at) = patch.expires_at {
+        link.expires_at = expires_at;
+    }
+    if let Some(fallback_url) = patch.fallback_url {
+        link.fallback_url = fallback_url;
+    }
+    if let Some(redirect_code) = patch.redirect_code {
+        validate_redirect_code(redirect_code).map_err(unprocessable)?;
+        link.redirect_code = redirect_code;
+    }
+    if let Some(prefix) = patch.prefix {
+        link.prefix = prefix;
+    }
+    if let Some(internal) = patch.internal {
+        link.internal = internal;
+    }
+    if let Some(skip_warning) = patch.skip_warning {
+        link.skip_warning = skip_warning;
+    }
+    if let Some(password) = patch.password {
+        validate_password(password.as_deref()).map_err(unprocessable)?;
+        link.pw_hash = password.as_deref().map(encrypt_pw);
+    }
+    if let Some(max_clicks) = patch.max_clicks {
+        validate_max_clicks(max_clicks).map_err(unprocessable)?;
+        link.limit_clicks(max_clicks);
+    }
+    validate_schedule(&link.schedule()).map_err(unprocessable)?;
+    let tags = patch.tags.map(normalize_tags);
+    if let Some(tags) = &tags {
+        validate_tags(tags).map_err(unprocessable)?;
+    }
+
+    let link = Link::save(short, link, patch.keep_alias, &conn).await?;
+    if let Some(tags) = tags {
+        Tag::set_for_link(link.short.clone(), tags, &conn).await?;
+    }
+    Ok(Json(LinkResponse::load(link, &conn).await?))
+}
+
+/* --------------------------------- aliases -------------------------------- */
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AliasResponse {
+    alias: String,
+    short: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<Alias> for AliasResponse {
+    fn from(alias: Alias) -> AliasResponse {
+        AliasResponse {
+            alias: alias.alias,
+            short: alias.short,
+            created_at: alias.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewAliasJson {
+    alias: String,
+}
+
+#[get("/<short>/aliases")]
+pub async fn aliases(
+    short: String,
+    user: User,
+    conn: DbConn,
+) -> ApiResult<Json<Vec<AliasResponse>>> {
+    check_can_edit(&user, &short, &conn).await?;
+    let aliases = Alias::all_for_link(short, &conn).await?;
+    Ok(Json(aliases.into_iter().map(AliasResponse::from).collect()))
+}
+
+#[post("/<short>/aliases", data = "<new_alias>")]
+pub async fn add_alias(
+    short: String,
+    new_alias: Json<NewAliasJson>,
+    user: User,
+    conn: DbConn,
+) -> ApiResult<Created<Json<AliasResponse>>> {
+    check_can_edit(&user, &short, &conn).await?;
+    let alias = new_alias.into_inner().alias;
+    validate_short(&alias).map_err(unprocessable)?;
+
+    let alias = Alias::insert(Alias::new(alias, short), &conn).await?;
+    let location = format!("/api/v1/links/{}/aliases/{}", alias.short, alias.alias);
+    Ok(Created::new(location).body(Json(alias.into())))
+}
+
+#[delete("/<short>/aliases/<alias>")]
+pub async fn remove_alias(
+    short: String,
+    alias: String,
+    user: User,
+    conn: DbConn,
+) -> ApiResult<NoContent> {
+    check_can_edit(&user, &short, &conn).await?;
+    Alias::delete(alias, short, &conn)
+        .await
+        .map_err(|err| match err {
+            Error::NotFound => ApiError::new(Status::NotFound, "That alias does not exist"),
+            err => err.into(),
+        })?;
+    Ok(NoContent)
+}
+
+/* --------------------------------- delete --------------------------------- */
+
+#[delete("/<short>")]
+pub async fn delete(short: String, user: User, conn: DbConn) -> ApiResult<NoContent> {
+    check_can_edit(&user, &short, &conn).await?;
+    Link::delete(short, &conn).await?;
+    Ok(NoContent)
+}
+
+/// A blank password would still have to be entered, so it's turned away instead of
+/// being taken as no password
+fn validate_password(password: Option<&str>) -> Result<(), &'static str> {
+    if password == Some("") {
+        return Err("A password can't be blank, use null for no password");
+    }
+
+    Ok(())
+}
+
+fn unprocessable(msg: &'static str) -> ApiError {
+    ApiError::new(Status::UnprocessableEntity, msg)
+}
@@ -0,0 +1,412 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::{DateTime, Utc};
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Header, Status};
+use rocket::serde::json::serde_json::{self, json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_dyn_templates::Template;
+use std::collections::HashSet;
+
+use diesel::result::{DatabaseErrorKind, Error};
+
+use crate::config::LinkrConfig;
+use crate::crypto::is_pw_hash;
+use crate::db::DbConn;
+use crate::models::links::Link;
+use crate::models::tags::Tag;
+use crate::models::users::User;
+use crate::routes::links::{
+    insert_with_random_short, parse_tags, validate_long, validate_max_clicks,
+    validate_redirect_code, validate_schedule, validate_short, validate_tags,
+};
+
+/// A link as it appears in an export, and as it's read back in an import
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LinkRecord {
+    #[serde(default)]
+    short: String,
+    long: String,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    active_from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    fallback_url: Option<String>,
+    #[serde(default)]
+    redirect_code: Option<i16>,
+    #[serde(default)]
+    prefix: bool,
+    #[serde(default)]
+    internal: bool,
+    #[serde(default)]
+    skip_warning: bool,
+    /// Only exported in full backups, as the hash so a link stays protected by the same password
+    #[serde(default)]
+    pw_hash: Option<String>,
+    #[serde(default)]
+    max_clicks: Option<i32>,
+    /// Defaults to the full limit when it's left out
+    #[serde(default)]
+    clicks_left: Option<i32>,
+    /// Comma separated so it fits in a single csv column
+    #[serde(default)]
+    tags: String,
+}
+
+impl LinkRecord {
+    fn new(link: Link, tags: &[String], backup: bool) -> LinkRecord {
+        LinkRecord {
+            short: link.short,
+            long: link.long,
+            notes: link.notes,
+            created_at: Some(link.created_at),
+            active_from: link.active_from,
+            expires_at: link.expires_at,
+            fallback_url: link.fallback_url,
+            redirect_code: Some(link.redirect_code),
+            prefix: link.prefix,
+            internal: link.internal,
+            skip_warning: link.skip_warning,
+            pw_hash: link.pw_hash.filter(|_| backup),
+            max_clicks: link.max_clicks,
+            clicks_left: link.clicks_left,
+            tags: tags.join(","),
+        }
+    }
+}
+
+#[derive(FromFormField, Clone, Copy)]
+pub enum Format {
+    #[field(value = "csv")]
+    Csv,
+    #[field(value = "json")]
+    Json,
+}
+
+/* --------------------------------- export --------------------------------- */
+
+#[derive(Responder)]
+pub struct Export {
+    body: String,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+/// Downloads the user's own links, or every link when `all` is set by someone who can manage links.
+/// Password hashes are left out unless it's a full `backup`.
+#[get("/export?<format>&<all>&<backup>")]
+pub async fn export(
+    format: Format,
+    all: Option<bool>,
+    backup: Option<bool>,
+    user: User,
+    conn: DbConn,
+) -> Result<Export, Status> {
+    let links = if all.unwrap_or(false) {
+        if !user.manage_links {
+            return Err(Status::Forbidden);
+        }
+        Link::all(&conn).await
+    } else {
+        Link::all_for_user(user.id, &conn).await
+    };
+    let links = links.map_err(|_| Status::InternalServerError)?;
+    let shorts = links.iter().map(|link| link.short.clone()).collect();
+    let tags = Tag::names_for_links(shorts, &conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let records: Vec<LinkRecord> = links
+        .into_iter()
+        .map(|link| {
+            let link_tags = tags.get(&link.short).map(Vec::as_slice).unwrap_or(&[]);
+            LinkRecord::new(link, link_tags, backup.unwrap_or(false))
+        })
+        .collect();
+
+    let (body, content_type, extension) = match format {
+        Format::Csv => (to_csv(&records)?, ContentType::CSV, "csv"),
+        Format::Json => match serde_json::to_string_pretty(&records) {
+            Ok(json) => (json, ContentType::JSON, "json"),
+            Err(_) => return Err(Status::InternalServerError),
+        },
+    };
+
+    let filename = format!("linkr-{}.{}", Utc::now().format("%F"), extension);
+    Ok(Export {
+        body,
+        content_type,
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ),
+    })
+}
+
+fn to_csv(records: &[LinkRecord]) -> Result<String, Status> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for record in records {
+        writer
+            .serialize(record)
+            .map_err(|_| Status::InternalServerError)?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|_| Status::InternalServerError)?;
+    String::from_utf8(bytes).map_err(|_| Status::InternalServerError)
+}
+
+/* --------------------------------- import --------------------------------- */
+
+#[derive(FromForm)]
+pub struct Import<'r> {
+    file: TempFile<'r>,
+    format: Format,
+    dry_run: bool,
+}
+
+/// The outcome of importing one row of the file
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ImportRow {
+    row: usize,
+    short: String,
+    success: bool,
+    result: String,
+}
+
+impl ImportRow {
+    fn success(row: usize, short: String, result: &str) -> ImportRow {
+        ImportRow {
+            row,
+            short,
+            success: true,
+            result: result.to_string(),
+        }
+    }
+
+    fn failure(row: usize, short: String, result: impl Into<String>) -> ImportRow {
+        ImportRow {
+            row,
+            short,
+            success: false,
+            result: result.into(),
+        }
+    }
+}
+
+#[post("/import", data = "<import_form>")]
+pub async fn import(
+    import_form: Form<Import<'_>>,
+    user: User,
+    config: &State<LinkrConfig>,
+    conn: DbConn,
+) -> Result<Template, Status> {
+    let import = import_form.into_inner();
+    let contents = read_upload(&import.file).await?;
+
+    let records = match parse_records(&contents, import.format) {
+        Ok(records) => records,
+        Err(msg) => {
+            let context = json!({
+                "user": user,
+                "dry_run": import.dry_run,
+                "error": msg,
+            });
+            return Ok(Template::render("pages/import_results", &context));
+        }
+    };
+
+    let mut rows = Vec::with_capacity(records.len());
+    let mut seen = HashSet::new();
+    for (index, record) in records.into_iter().enumerate() {
+        // the header takes up the first row of a csv
+        let row = match import.format {
+            Format::Csv => index + 2,
+            Format::Json => index + 1,
+        };
+        let record = match record {
+            Ok(record) => record,
+            Err(msg) => {
+                rows.push(ImportRow::failure(row, String::new(), msg));
+                continue;
+            }
+        };
+        let result = import_record(record, row, import.dry_run, &mut seen, &user, config, &conn);
+        rows.push(result.await);
+    }
+
+    let created = rows.iter().filter(|row| row.success).count();
+    let context = json!({
+        "user": user,
+        "dry_run": import.dry_run,
+        "created": created,
+        "failed": rows.len() - created,
+        "rows": rows,
+    });
+    Ok(Template::render("pages/import_results", &context))
+}
+
+async fn import_record(
+    record: LinkRecord,
+    row: usize,
+    dry_run: bool,
+    seen: &mut HashSet<String>,
+    user: &User,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> ImportRow {
+    // the same rules as creating a link by hand
+    let random_short = record.short.is_empty();
+    let valid = if random_short {
+        validate_long(&record.long)
+    } else {
+        validate_short(&record.short).and(validate_long(&record.long))
+    };
+    if let Err(msg) = valid {
+        return ImportRow::failure(row, record.short, msg);
+    }
+
+    let redirect_code = record.redirect_code.unwrap_or(config.default_redirect_code);
+    let mut link = Link::new(
+        record.short,
+        record.long,
+        record.notes,
+        user.id,
+        redirect_code,
+    );
+    if let Some(created_at) = record.created_at {
+        link.created_at = created_at;
+    }
+    link.active_from = record.active_from;
+    link.expires_at = record.expires_at;
+    link.fallback_url = record.fallback_url;
+    link.prefix = record.prefix;
+    link.internal = record.internal;
+    link.skip_warning = record.skip_warning;
+    link.pw_hash = record.pw_hash.filter(|pw_hash| !pw_hash.is_empty());
+    if link
+        .pw_hash
+        .as_deref()
+        .is_some_and(|pw_hash| !is_pw_hash(pw_hash))
+    {
+        return ImportRow::failure(row, link.short, "Invalid password hash");
+    }
+    if let Err(msg) =
+        validate_schedule(&link.schedule()).and(validate_redirect_code(link.redirect_code))
+    {
+        return ImportRow::failure(row, link.short, msg);
+    }
+    if let Err(msg) = validate_max_clicks(record.max_clicks) {
+        return ImportRow::failure(row, link.short, msg);
+    }
+    link.limit_clicks(record.max_clicks);
+    if let Some(max_clicks) = link.max_clicks {
+        match record.clicks_left {
+            Some(left) if left < 0 || left > max_clicks => {
+                return ImportRow::failure(row, link.short, "Invalid clicks left");
+            }
+            Some(left) => link.clicks_left = Some(left),
+            None => {}
+        }
+    }
+    let tags = parse_tags(&record.tags);
+    if let Err(msg) = validate_tags(&tags) {
+        return ImportRow::failure(row, link.short, msg);
+    }
+
+    if !random_short && !seen.insert(link.short.clone()) {
+        return ImportRow::failure(row, link.short, "Conflict: repeated earlier in the file");
+    }
+
+    if dry_run {
+        if random_short {
+            return ImportRow::success(row, link.short, "Would be created with a random short");
+        }
+        // aliases take up shorts too
+        return match Link::resolve(link.short.clone(), conn).await {
+            Ok(_) => ImportRow::failure(row, link.short, "Conflict: short already in use"),
+            Err(Error::NotFound) => ImportRow::success(row, link.short, "Would be created"),
+            Err(_) => ImportRow::failure(row, link.short, "Internal server error"),
+        };
+    }
+
+    let short = link.short.clone();
+    let result = if random_short {
+        insert_with_random_short(link, config, conn).await
+    } else {
+        Link::insert(link, conn).await
+    };
+    let link = match result {
+        Ok(link) => link,
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return ImportRow::failure(row, short, "Conflict: short already in use")
+        }
+        Err(_) => return ImportRow::failure(row, short, "Internal server error"),
+    };
+    match Tag::set_for_link(link.short.clone(), tags, conn).await {
+        Ok(_) => ImportRow::success(row, link.short, "Created"),
+        Err(_) => ImportRow::failure(row, link.short, "Created, but the tags could not be set"),
+    }
+}
+
+async fn read_upload(file: &TempFile<'_>) -> Result<String, Status> {
+    match file {
+        TempFile::Buffered { content } => Ok(content.to_string()),
+        TempFile::File { .. } => {
+            let path = file.path().ok_or(Status::InternalServerError)?;
+            rocket::tokio::fs::read_to_string(path)
+                .await
+                .map_err(|_| Status::UnprocessableEntity)
+        }
+    }
+}
+
+/// Parses every record in the file, keeping rows that can't be read as errors
+/// so the rest of the file can still be imported
+fn parse_records(
+    contents: &str,
+    format: Format,
+) -> Result<Vec<Result<LinkRecord, String>>, String> {
+    match format {
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(contents.as_bytes());
+            Ok(reader
+                .deserialize()
+                .map(|record| record.map_err(|err| format!("Invalid row: {}", err)))
+                .collect())
+        }
+        Format::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_str(contents)
+                .map_err(|err| format!("The file is not a JSON array: {}", err))?;
+            Ok(values
+                .into_iter()
+                .map(|value| {
+                    serde_json::from_value(value).map_err(|err| format!("Invalid row: {}", err))
+                })
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::Utc;
+use diesel::QueryResult;
+use rocket::http::RawStr;
+use rocket::serde::json::serde_json::json;
+use rocket::serde::json::Value;
+use rocket::serde::Serialize;
+
+use crate::db::DbConn;
+use crate::models::clicks::Click;
+use crate::models::links::{Link, LinkQuery, LinkSort, SortDirection};
+use crate::models::tags::Tag;
+
+const LINKS_PER_PAGE: i64 = 50;
+
+/// The search, filters, sort and page of a link table.
+/// They're kept in the query string so a view can be bookmarked.
+#[derive(FromForm, Clone)]
+pub struct TableParams {
+    q: Option<String>,
+    tag: Option<String>,
+    sort: Option<LinkSort>,
+    dir: Option<SortDirection>,
+    page: Option<i64>,
+}
+
+impl TableParams {
+    fn search(&self) -> Option<String> {
+        self.q
+            .as_ref()
+            .map(|q| q.trim().to_string())
+            .filter(|q| !q.is_empty())
+    }
+
+    fn tag(&self) -> Option<String> {
+        self.tag.clone().filter(|tag| !tag.is_empty())
+    }
+
+    fn sort(&self) -> LinkSort {
+        self.sort.unwrap_or(LinkSort::Created)
+    }
+
+    fn direction(&self) -> SortDirection {
+        self.dir.unwrap_or_else(|| self.sort().default_direction())
+    }
+
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn query(&self, owner: Option<i32>) -> LinkQuery {
+        LinkQuery {
+            owner,
+            search: self.search(),
+            tag: self.tag(),
+            sort: self.sort(),
+            direction: self.direction(),
+        }
+    }
+
+    /// A relative url for this view of the table
+    fn url(&self) -> String {
+        let mut params = vec![];
+        if let Some(search) = self.search() {
+            params.push(format!("q={}", RawStr::new(&search).percent_encode()));
+        }
+        if let Some(tag) = self.tag() {
+            params.push(format!("tag={}", RawStr::new(&tag).percent_encode()));
+        }
+        if let Some(sort) = self.sort {
+            params.push(format!("sort={}", sort.as_str()));
+        }
+        if let Some(dir) = self.dir {
+            params.push(format!("dir={}", dir.as_str()));
+        }
+        if self.page() > 1 {
+            params.push(format!("page={}", self.page()));
+        }
+        format!("?{}", params.join("&"))
+    }
+
+    fn with_page(&self, page: i64) -> String {
+        TableParams {
+            page: Some(page),
+            ..self.clone()
+        }
+        .url()
+    }
+
+    fn with_tag(&self, tag: Option<String>) -> String {
+        TableParams {
+            tag,
+            page: None,
+            ..self.clone()
+        }
+        .url()
+    }
+
+    /// Sorting by the current column again flips the direction
+    fn with_sort(&self, sort: LinkSort) -> String {
+        let dir = match self.direction() {
+            SortDirection::Asc if sort == self.sort() => SortDirection::Desc,
+            SortDirection::Desc if sort == self.sort() => SortDirection::Asc,
+            _ => sort.default_direction(),
+        };
+        TableParams {
+            sort: Some(sort),
+            dir: Some(dir),
+            page: None,
+            ..self.clone()
+        }
+        .url()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LinkRow {
+    #[serde(flatten)]
+    link: Link,
+    clicks: i64,
+    status: &'static str,
+    protected: bool,
+    tags: Vec<String>,
+}
+
+/// Loads one page of a link table, limited to the owner's links when there is one,
+/// along with everything the template needs to render and navigate it
+pub async fn link_table(
+    owner: Option<i32>,
+    params: TableParams,
+    conn: &DbConn,
+) -> QueryResult<Value> {
+    let query = params.query(owner);
+    let total = Link::count_matching(query.clone(), conn).await?;
+    let pages = ((total + LINKS_PER_PAGE - 1) / LINKS_PER_PAGE).max(1);
+    // past the last page shows the last page instead of running off the end
+    let page = params.page().min(pages);
+    let links = Link::page(query, LINKS_PER_PAGE, (page - 1) * LINKS_PER_PAGE, conn).await?;
+
+    let shorts: Vec<String> = links.iter().map(|link| link.short.clone()).collect();
+    let clicks = Click::totals(shorts.clone(), conn).await?;
+    let mut tags = Tag::names_for_links(shorts, conn).await?;
+    let now = Utc::now();
+    let rows: Vec<LinkRow> = links
+        .into_iter()
+        .map(|link| LinkRow {
+            clicks: clicks.get(&link.short).copied().unwrap_or(0),
+            status: link.status(now).as_str(),
+            protected: link.is_protected(),
+            tags: tags.remove(&link.short).unwrap_or_default(),
+            link,
+        })
+        .collect();
+
+    let all_tags = match owner {
+        Some(user_id) => Tag::all_for_user(user_id, conn).await?,
+        None => Tag::all(conn).await?,
+    };
+    let current_tag = params.tag();
+    let tag_filters: Vec<Value> = all_tags
+        .into_iter()
+        .map(|tag| {
+            json!({
+                "url": params.with_tag(Some(tag.name.clone())),
+                "selected": current_tag.as_ref() == Some(&tag.name),
+                "name": tag.name,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "links": rows,
+        "tags": tag_filters,
+        "all_tags_url": params.with_tag(None),
+        "search": params.search(),
+        "tag": current_tag,
+        "sort": params.sort.map(|sort| sort.as_str()),
+        "dir": params.dir.map(|dir| dir.as_str()),
+        "sort_urls": {
+            "short": params.with_sort(LinkSort::Short),
+            "long": params.with_sort(LinkSort::Long),
+            "created": params.with_sort(LinkSort::Created),
+        },
+        "sorted_by": params.sort().as_str(),
+        "descending": params.direction() == SortDirection::Desc,
+        "total": total,
+        "page": page,
+        "pages": pages,
+        "prev_url": (page > 1).then(|| params.with_page(page - 1)),
+        "next_url": (page < pages).then(|| params.with_page(page + 1)),
+    }))
+}
@@ -16,29 +16,58 @@
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
 use rocket::form::Form;
-use rocket::http::Status;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
 use rocket::response::{Flash, Redirect};
+use rocket::State;
 
-use chrono::Utc;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use diesel::result::DatabaseErrorKind;
 use diesel::result::Error;
+use diesel::QueryResult;
 
+use crate::auth::{release_unlock_attempt, reserve_unlock_attempt, safe_return_to};
+use crate::client::ClientInfo;
+use crate::config::LinkrConfig;
+use crate::crypto::encrypt_pw;
 use crate::db::DbConn;
-use crate::models::links::Link;
+use crate::models::aliases::Alias;
+use crate::models::links::{Link, LinkSchedule, REDIRECT_CODES};
+use crate::models::tags::Tag;
 use crate::models::users::User;
+use crate::random_short::ShortGenerator;
+use crate::routes::pages::unlock_cookie_name;
+use crate::url_template;
 
 #[derive(FromForm)]
 pub struct NewLink {
     short: String,
     long: String,
     notes: String,
+    #[field(default = "")]
+    active_from: String,
+    #[field(default = "")]
+    expires_at: String,
+    #[field(default = "")]
+    fallback_url: String,
+    redirect_code: Option<i16>,
+    #[field(default = "")]
+    tags: String,
+    prefix: bool,
+    internal: bool,
+    skip_warning: bool,
+    /// Left blank for a link anyone can follow
+    #[field(default = "")]
+    password: String,
+    /// Left blank for a link that can be followed any number of times
+    max_clicks: Option<i32>,
 }
 
-const RESERVED_LINKS: [&str; 9] = [
+const RESERVED_LINKS: [&str; 10] = [
     "",
     "api",
     "login",
     "resource",
+    "details",
     "new_user",
     "setup",
     "manage_links",
@@ -46,55 +75,88 @@ const RESERVED_LINKS: [&str; 9] = [
     "manage_account",
 ];
 
+/// How many random shorts to try before giving up
+const RANDOM_SHORT_ATTEMPTS: usize = 10;
+
+/// How many collisions at one length before random shorts get longer
+const COLLISIONS_PER_LENGTH: usize = 3;
+
 #[post("/new", data = "<link_form>")]
 pub async fn shorten(
     conn: DbConn,
     link_form: Form<NewLink>,
     user: User,
+    config: &State<LinkrConfig>,
 ) -> Result<Flash<Redirect>, Status> {
     let new_link = link_form.into_inner();
 
-    // check if the short is alphanumeric
-    if !new_link.short.chars().all(char::is_alphanumeric) {
-        return Ok(Flash::error(
-            Redirect::to("/"),
-            "Shorts can only contain alphanumeric characters",
-        ));
+    // a blank short gets a random one generated
+    let random_short = new_link.short.is_empty();
+    let valid = if random_short {
+        validate_long(&new_link.long)
+    } else {
+        validate_short(&new_link.short).and(validate_long(&new_link.long))
+    };
+    if let Err(msg) = valid {
+        return Ok(Flash::error(Redirect::to("/"), msg));
     }
+    let schedule = match parse_schedule(
+        &new_link.active_from,
+        &new_link.expires_at,
+        &new_link.fallback_url,
+    ) {
+        Ok(schedule) => schedule,
+        Err(msg) => return Ok(Flash::error(Redirect::to("/"), msg)),
+    };
 
-    // check if the short is reserved by this site
-    if RESERVED_LINKS
-        .iter()
-        .any(|&reserved| reserved == new_link.short)
-    {
-        return Ok(Flash::error(
-            Redirect::to("/"),
-            "That short is reserved by this website",
-        ));
+    let redirect_code = new_link
+        .redirect_code
+        .unwrap_or(config.default_redirect_code);
+    if let Err(msg) = validate_redirect_code(redirect_code) {
+        return Ok(Flash::error(Redirect::to("/"), msg));
     }
 
-    // check that the long is a valid url
-    let prefix_correct =
-        new_link.long.starts_with("http://") || new_link.long.starts_with("https://");
-    if !prefix_correct {
-        return Ok(Flash::error(
-            Redirect::to("/"),
-            "That long does not begin with https:// or http://",
-        ));
+    let tags = parse_tags(&new_link.tags);
+    if let Err(msg) = validate_tags(&tags).and(validate_max_clicks(new_link.max_clicks)) {
+        return Ok(Flash::error(Redirect::to("/"), msg));
     }
 
     // create link to insert
-    let link = Link {
-        short: new_link.short,
-        long: new_link.long,
-        notes: new_link.notes,
-        created_at: Utc::now(),
-        created_by: user.id,
-    };
+    let mut link = Link::new(
+        new_link.short,
+        new_link.long,
+        new_link.notes,
+        user.id,
+        redirect_code,
+    );
+    link.prefix = new_link.prefix;
+    link.internal = new_link.internal;
+    link.skip_warning = new_link.skip_warning;
+    link.limit_clicks(new_link.max_clicks);
+    if !new_link.password.is_empty() {
+        link.pw_hash = Some(encrypt_pw(&new_link.password));
+    }
+    link.active_from = schedule.active_from;
+    link.expires_at = schedule.expires_at;
+    link.fallback_url = schedule.fallback_url;
 
     // send database request and respond accordingly
-    match Link::insert(link, &conn).await {
-        Ok(_) => Ok(Flash::success(Redirect::to("/"), "Link created!")),
+    let result = if random_short {
+        insert_with_random_short(link, config, &conn).await
+    } else {
+        Link::insert(link, &conn).await
+    };
+    let result = match result {
+        Ok(link) => Tag::set_for_link(link.short.clone(), tags, &conn)
+            .await
+            .map(|_| link),
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(link) => Ok(Flash::success(
+            Redirect::to("/"),
+            format!("Link created: /{}", link.short),
+        )),
         Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(Flash::error(
             Redirect::to("/"),
             "That short is already in use",
@@ -106,6 +168,166 @@ pub async fn shorten(
     }
 }
 
+/// Inserts a link under a randomly generated short, retrying when the short is taken.
+/// The shorts start longer as more links exist and grow when one length keeps colliding.
+pub async fn insert_with_random_short(
+    mut link: Link,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> QueryResult<Link> {
+    let generator = ShortGenerator::new(config);
+    let taken = Link::count(None, conn).await? + Alias::count(conn).await?;
+    let mut length = generator.length_for(taken);
+    let mut collisions = 0;
+
+    for _ in 0..RANDOM_SHORT_ATTEMPTS {
+        link.short = generator.generate(length);
+        if validate_short(&link.short).is_err() {
+            continue;
+        }
+
+        match Link::insert(link.clone(), conn).await {
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                collisions += 1;
+                if collisions % COLLISIONS_PER_LENGTH == 0 {
+                    length += 1;
+                }
+            }
+            result => return result,
+        }
+    }
+
+    Err(Error::DatabaseError(
+        DatabaseErrorKind::UniqueViolation,
+        Box::new(String::from("could not find an unused random short")),
+    ))
+}
+
+/// Checks a short against the rules every new link has to follow
+pub fn validate_short(short: &str) -> Result<(), &'static str> {
+    // check if the short is alphanumeric
+    if !short.chars().all(char::is_alphanumeric) {
+        return Err("Shorts can only contain alphanumeric characters");
+    }
+
+    // check if the short is reserved by this site
+    if RESERVED_LINKS.contains(&short) {
+        return Err("That short is reserved by this website");
+    }
+
+    Ok(())
+}
+
+/// Checks that a long is a valid url to redirect to
+pub fn validate_long(long: &str) -> Result<(), &'static str> {
+    if !long.starts_with("http://") && !long.starts_with("https://") {
+        return Err("That long does not begin with https:// or http://");
+    }
+
+    // placeholders have to be ones that can be filled in safely
+    url_template::validate(long)?;
+
+    Ok(())
+}
+
+pub fn validate_redirect_code(code: i16) -> Result<(), &'static str> {
+    if !REDIRECT_CODES.contains(&code) {
+        return Err("Links can only redirect with a 301, 302, 307 or 308");
+    }
+
+    Ok(())
+}
+
+/// Checks that a link doesn't expire before it becomes active
+/// and that its fallback is a valid url
+pub fn validate_schedule(schedule: &LinkSchedule) -> Result<(), &'static str> {
+    if let (Some(from), Some(until)) = (schedule.active_from, schedule.expires_at) {
+        if until <= from {
+            return Err("A link cannot expire before it becomes active");
+        }
+    }
+
+    if let Some(fallback) = &schedule.fallback_url {
+        validate_long(fallback)?;
+        if url_template::has_placeholders(fallback) {
+            return Err("A fallback url cannot have placeholders");
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds a schedule from form inputs, where blank inputs are left unset
+fn parse_schedule(
+    active_from: &str,
+    expires_at: &str,
+    fallback_url: &str,
+) -> Result<LinkSchedule, &'static str> {
+    let schedule = LinkSchedule {
+        active_from: parse_form_date(active_from)?,
+        expires_at: parse_form_date(expires_at)?,
+        fallback_url: Some(fallback_url.to_string()).filter(|url| !url.is_empty()),
+    };
+    validate_schedule(&schedule)?;
+    Ok(schedule)
+}
+
+/// Parses the value of a `datetime-local` input, which is taken to be in UTC
+fn parse_form_date(date: &str) -> Result<Option<DateTime<Utc>>, &'static str> {
+    if date.is_empty() {
+        return Ok(None);
+    }
+
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S"))
+        .map(|date| Some(DateTime::from_utc(date, Utc)))
+        .map_err(|_| "Dates must be formatted like 2021-01-31T13:30")
+}
+
+/// Splits comma separated tags into a sorted list without duplicates or blanks
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    normalize_tags(tags.split(','))
+}
+
+/// Trims and lowercases tag names, dropping blanks and duplicates
+pub fn normalize_tags<I, S>(tags: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut names: Vec<String> = tags
+        .into_iter()
+        .map(|name| name.as_ref().trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Checks that tag names are short and only use url safe characters
+pub fn validate_tags(names: &[String]) -> Result<(), &'static str> {
+    let valid = names.iter().all(|name| {
+        name.len() <= 32
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    });
+    if !valid {
+        return Err("Tags can only contain letters, numbers, - and _ and be up to 32 characters");
+    }
+
+    Ok(())
+}
+
+pub fn validate_max_clicks(max_clicks: Option<i32>) -> Result<(), &'static str> {
+    if max_clicks.is_some_and(|max| max < 1) {
+        return Err("A click limit must be at least 1");
+    }
+
+    Ok(())
+}
+
 #[derive(FromForm)]
 pub struct Short {
     short: String,
@@ -134,23 +356,305 @@ pub struct UpdateLong {
 }
 
 #[post("/update", data = "<update_form>")]
-pub async fn update(conn: DbConn, update_form: Form<UpdateLong>, user: User) -> Status {
+pub async fn update(
+    conn: DbConn,
+    update_form: Form<UpdateLong>,
+    user: User,
+) -> Result<Status, (Status, &'static str)> {
     let update = update_form.into_inner();
 
-    match check_can_edit(&user, &update.short, &conn).await {
-        Ok(_) => {}
-        Err(err) => return err,
+    if let Err(err) = check_can_edit(&user, &update.short, &conn).await {
+        return Err((err, ""));
     }
 
+    validate_long(&update.long).map_err(|msg| (Status::UnprocessableEntity, msg))?;
+
     match Link::update(update.short.to_string(), update.long.to_string(), &conn).await {
+        Ok(_) => Ok(Status::Ok),
+        Err(Error::NotFound) => Err((Status::NotFound, "")),
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err((Status::Conflict, ""))
+        }
+        Err(_) => Err((Status::InternalServerError, "")),
+    }
+}
+
+#[derive(FromForm)]
+pub struct EditLink {
+    short: String,
+    new_short: String,
+    long: String,
+    notes: String,
+    prefix: bool,
+    internal: bool,
+    skip_warning: bool,
+    keep_alias: bool,
+    /// Left blank to keep the current password
+    #[field(default = "")]
+    password: String,
+    remove_password: bool,
+    /// Changing the limit starts the count over
+    max_clicks: Option<i32>,
+}
+
+/// Changes a link's short, long and notes together, keeping its creator and history
+#[post("/edit", data = "<edit_form>")]
+pub async fn edit(
+    conn: DbConn,
+    edit_form: Form<EditLink>,
+    user: User,
+) -> Result<Flash<Redirect>, Status> {
+    let edit = edit_form.into_inner();
+    check_can_edit(&user, &edit.short, &conn).await?;
+
+    let back = Redirect::to(format!("/details/{}", edit.short));
+    let renamed = edit.new_short != edit.short;
+    let valid = if renamed {
+        validate_short(&edit.new_short).and(validate_long(&edit.long))
+    } else {
+        validate_long(&edit.long)
+    };
+    if let Err(msg) = valid.and(validate_max_clicks(edit.max_clicks)) {
+        return Ok(Flash::error(back, msg));
+    }
+
+    let mut link = match Link::get(edit.short.clone(), &conn).await {
+        Ok(link) => link,
+        Err(Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    link.short = edit.new_short;
+    link.long = edit.long;
+    link.notes = edit.notes;
+    link.prefix = edit.prefix;
+    link.internal = edit.internal;
+    link.skip_warning = edit.skip_warning;
+    if edit.remove_password {
+        link.pw_hash = None;
+    } else if !edit.password.is_empty() {
+        link.pw_hash = Some(encrypt_pw(&edit.password));
+    }
+    if edit.max_clicks != link.max_clicks {
+        link.limit_clicks(edit.max_clicks);
+    }
+
+    match Link::save(edit.short, link, edit.keep_alias, &conn).await {
+        Ok(link) => Ok(Flash::success(
+            Redirect::to(format!("/details/{}", link.short)),
+            "Link updated",
+        )),
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Ok(Flash::error(back, "That short is already in use"))
+        }
+        Err(_) => Ok(Flash::error(back, "There was an internal server error")),
+    }
+}
+
+#[derive(FromForm)]
+pub struct AliasForm {
+    short: String,
+    alias: String,
+}
+
+#[post("/aliases/add", data = "<alias_form>")]
+pub async fn add_alias(
+    conn: DbConn,
+    alias_form: Form<AliasForm>,
+    user: User,
+) -> Result<Flash<Redirect>, Status> {
+    let form = alias_form.into_inner();
+    check_can_edit(&user, &form.short, &conn).await?;
+
+    let back = Redirect::to(format!("/details/{}", form.short));
+    if let Err(msg) = validate_short(&form.alias) {
+        return Ok(Flash::error(back, msg));
+    }
+
+    match Alias::insert(Alias::new(form.alias, form.short), &conn).await {
+        Ok(alias) => Ok(Flash::success(
+            back,
+            format!("Alias created: /{}", alias.alias),
+        )),
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Ok(Flash::error(back, "That short is already in use"))
+        }
+        Err(_) => Ok(Flash::error(back, "There was an internal server error")),
+    }
+}
+
+#[post("/aliases/remove", data = "<alias_form>")]
+pub async fn remove_alias(
+    conn: DbConn,
+    alias_form: Form<AliasForm>,
+    user: User,
+) -> Result<Flash<Redirect>, Status> {
+    let form = alias_form.into_inner();
+    check_can_edit(&user, &form.short, &conn).await?;
+
+    let back = Redirect::to(format!("/details/{}", form.short));
+    match Alias::delete(form.alias, form.short, &conn).await {
+        Ok(_) => Ok(Flash::success(back, "Alias removed")),
+        Err(Error::NotFound) => Err(Status::NotFound),
+        Err(_) => Ok(Flash::error(back, "There was an internal server error")),
+    }
+}
+
+#[derive(FromForm)]
+pub struct Unlock {
+    short: String,
+    password: String,
+    next: String,
+}
+
+/// Remembers that this browser knows a link's password, then goes back to following it
+#[post("/unlock", data = "<unlock_form>")]
+pub async fn unlock(
+    conn: DbConn,
+    unlock_form: Form<Unlock>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let unlock = unlock_form.into_inner();
+
+    // only ever go back to a page on this site
+    let next = match safe_return_to(&unlock.next) {
+        Some(next) => next.to_string(),
+        None => format!("/{}", unlock.short),
+    };
+
+    let link = match Link::resolve(unlock.short, &conn).await {
+        Ok(link) => link,
+        Err(_) => return Err(Flash::error(Redirect::to(next), "That link does not exist")),
+    };
+
+    // throttled like logins, so guesses can't be checked as fast as the server can hash them
+    match reserve_unlock_attempt(&link.short, &client, config, &conn).await {
+        Ok(None) => {}
+        Ok(Some(msg)) => return Err(Flash::error(Redirect::to(next), msg)),
+        Err(_) => {
+            return Err(Flash::error(
+                Redirect::to(next),
+                "There was an internal server error",
+            ))
+        }
+    }
+    if !link.verify_password(&unlock.password) {
+        return Err(Flash::error(Redirect::to(next), "Incorrect password"));
+    }
+    if release_unlock_attempt(&link.short, &client, &conn)
+        .await
+        .is_err()
+    {
+        return Err(Flash::error(
+            Redirect::to(next),
+            "There was an internal server error",
+        ));
+    }
+
+    if let Some(token) = link.unlock_token() {
+        // lax so it's still sent when the link is followed from another site
+        let cookie = Cookie::build(unlock_cookie_name(&link), token)
+            .same_site(SameSite::Lax)
+            .finish();
+        cookies.add_private(cookie);
+    }
+    Ok(Redirect::to(next))
+}
+
+#[derive(FromForm)]
+pub struct UpdateTags {
+    short: String,
+    tags: String,
+}
+
+#[post("/update/tags", data = "<tags_form>")]
+pub async fn update_tags(
+    conn: DbConn,
+    tags_form: Form<UpdateTags>,
+    user: User,
+) -> Result<Status, (Status, &'static str)> {
+    let update = tags_form.into_inner();
+
+    if let Err(err) = check_can_edit(&user, &update.short, &conn).await {
+        return Err((err, ""));
+    }
+
+    let tags = parse_tags(&update.tags);
+    validate_tags(&tags).map_err(|msg| (Status::UnprocessableEntity, msg))?;
+
+    match Tag::set_for_link(update.short, tags, &conn).await {
+        Ok(_) => Ok(Status::Ok),
+        Err(Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            Err((Status::NotFound, ""))
+        }
+        Err(_) => Err((Status::InternalServerError, "")),
+    }
+}
+
+#[derive(FromForm)]
+pub struct UpdateRedirectCode {
+    short: String,
+    redirect_code: i16,
+}
+
+#[post("/update/redirect", data = "<redirect_form>")]
+pub async fn update_redirect_code(
+    conn: DbConn,
+    redirect_form: Form<UpdateRedirectCode>,
+    user: User,
+) -> Status {
+    let update = redirect_form.into_inner();
+
+    if let Err(err) = check_can_edit(&user, &update.short, &conn).await {
+        return err;
+    }
+    if validate_redirect_code(update.redirect_code).is_err() {
+        return Status::UnprocessableEntity;
+    }
+
+    match Link::update_redirect_code(update.short, update.redirect_code, &conn).await {
         Ok(_) => Status::Ok,
         Err(Error::NotFound) => Status::NotFound,
-        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Status::Conflict,
         Err(_) => Status::InternalServerError,
     }
 }
 
-async fn check_can_edit(user: &User, short: &str, conn: &DbConn) -> Result<(), Status> {
+#[derive(FromForm)]
+pub struct UpdateSchedule {
+    short: String,
+    active_from: String,
+    expires_at: String,
+    fallback_url: String,
+}
+
+#[post("/update/schedule", data = "<schedule_form>")]
+pub async fn update_schedule(
+    conn: DbConn,
+    schedule_form: Form<UpdateSchedule>,
+    user: User,
+) -> Result<Status, (Status, &'static str)> {
+    let update = schedule_form.into_inner();
+
+    if let Err(err) = check_can_edit(&user, &update.short, &conn).await {
+        return Err((err, ""));
+    }
+
+    let schedule = parse_schedule(
+        &update.active_from,
+        &update.expires_at,
+        &update.fallback_url,
+    )
+    .map_err(|msg| (Status::UnprocessableEntity, msg))?;
+
+    match Link::update_schedule(update.short, schedule, &conn).await {
+        Ok(_) => Ok(Status::Ok),
+        Err(Error::NotFound) => Err((Status::NotFound, "")),
+        Err(_) => Err((Status::InternalServerError, "")),
+    }
+}
+
+pub async fn check_can_edit(user: &User, short: &str, conn: &DbConn) -> Result<(), Status> {
     if !user.manage_links {
         let link_user = match Link::get(short.to_string(), &conn).await {
             Ok(link) => link.created_by,
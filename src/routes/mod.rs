@@ -15,7 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
+pub mod api;
+pub mod import_export;
+pub mod link_table;
 pub mod links;
+pub mod oidc;
 pub mod pages;
+pub mod qr;
 pub mod static_files;
+pub mod tokens;
+pub mod two_factor;
 pub mod users;
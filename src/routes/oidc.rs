@@ -0,0 +1,261 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use rocket::http::{Cookie, CookieJar, RawStr, SameSite};
+use rocket::response::{Flash, Redirect};
+use rocket::serde::json::serde_json::{self, json};
+use rocket::State;
+use rocket_dyn_templates::Template;
+use rocket_sync_db_pools::diesel::result::{DatabaseErrorKind, Error};
+
+use crate::auth::{login_path, login_redirect, pending_login, safe_return_to, start_second_step};
+use crate::client::{BaseUrl, ClientInfo};
+use crate::config::LinkrConfig;
+use crate::db::DbConn;
+use crate::models::users::{InsertableUser, User};
+use crate::oidc::{self, Identity, LoginRequest, OidcResult};
+use crate::routes::users::complete_login;
+
+/// How long someone has to log in with the identity provider, in minutes
+const LOGIN_TIMEOUT: i64 = 10;
+
+fn redirect_uri(base_url: &BaseUrl) -> String {
+    format!("{}/api/oidc/callback", base_url.0)
+}
+
+/* ---------------------------------- login --------------------------------- */
+
+/// Sends someone off to log in with the identity provider
+#[get("/login?<next>")]
+pub async fn login(
+    next: Option<String>,
+    base_url: BaseUrl,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let next = next.as_deref().and_then(safe_return_to).map(String::from);
+    let request = LoginRequest::new(next.clone(), None);
+    start(request, &base_url, cookies, config)
+        .await
+        .map_err(|msg| Flash::error(login_redirect(next.as_deref()), msg))
+}
+
+/// Sends someone who's logged in off to the identity provider to connect their account to it
+#[get("/connect")]
+pub async fn connect(
+    user: User,
+    base_url: BaseUrl,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let request = LoginRequest::new(None, Some(user.id));
+    start(request, &base_url, cookies, config)
+        .await
+        .map_err(|msg| Flash::error(Redirect::to("/manage_account"), msg))
+}
+
+async fn start(
+    request: LoginRequest,
+    base_url: &BaseUrl,
+    cookies: &CookieJar<'_>,
+    config: &LinkrConfig,
+) -> OidcResult<Redirect> {
+    let issuer = config
+        .oidc_issuer
+        .as_deref()
+        .ok_or("Single sign-on isn't set up")?;
+    let provider = oidc::discover(issuer).await?;
+    let url = oidc::authorization_url(&provider, config, &redirect_uri(base_url), &request)?;
+
+    // lax so it comes back with the identity provider's redirect to the callback
+    let value = serde_json::to_string(&request).map_err(|_| "An internal server error occurred")?;
+    cookies.add_private(
+        Cookie::build("oidc_login", value)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::minutes(LOGIN_TIMEOUT))
+            .finish(),
+    );
+    Ok(Redirect::to(url))
+}
+
+#[derive(FromForm)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Where the identity provider sends people back to.
+///
+/// Rocket's cookies are strict, so browsers leave them off the rest of a redirect that came
+/// from another site. This page moves on from here instead, which the login and its flash
+/// message are sent with.
+#[get("/callback?<params..>")]
+pub async fn callback(
+    params: CallbackParams,
+    base_url: BaseUrl,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    conn: DbConn,
+) -> Result<Template, Flash<Template>> {
+    let request: LoginRequest = match cookies
+        .get_private("oidc_login")
+        .and_then(|cookie| serde_json::from_str(cookie.value()).ok())
+    {
+        Some(request) => request,
+        None => {
+            return Err(Flash::error(
+                continue_page("/login"),
+                "Your login timed out, try again",
+            ))
+        }
+    };
+    cookies.remove_private(Cookie::named("oidc_login"));
+
+    let next = request.next.as_deref();
+    let back = match request.connect {
+        Some(_) => String::from("/manage_account"),
+        None => login_path(next),
+    };
+    let failure = |msg| Flash::error(continue_page(&back), msg);
+    if params.error.is_some() {
+        return Err(failure("The identity provider didn't log you in"));
+    }
+    // the state ties the callback to the login this browser started
+    if params.state.as_deref() != Some(request.state.as_str()) {
+        return Err(failure("Your login timed out, try again"));
+    }
+    let code = params
+        .code
+        .ok_or_else(|| failure("The identity provider didn't log you in"))?;
+
+    let identity = verify(&code, &request, &base_url, config)
+        .await
+        .map_err(failure)?;
+    if let Some(user_id) = request.connect {
+        return match User::set_oidc_subject(user_id, Some(identity.subject), &conn).await {
+            Ok(_) => Ok(continue_page("/manage_account")),
+            Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(failure("That identity is connected to another account"))
+            }
+            Err(_) => Err(failure("An internal server error occurred")),
+        };
+    }
+    let user = find_user(&identity, config, &conn).await.map_err(failure)?;
+    if user.disabled {
+        return Err(failure("That user is disabled"));
+    }
+
+    start_second_step(cookies, user.id);
+    Ok(continue_page(&match next {
+        Some(next) => format!(
+            "/api/oidc/finish?next={}",
+            RawStr::new(next).percent_encode()
+        ),
+        None => String::from("/api/oidc/finish"),
+    }))
+}
+
+/// Finishes logging in from the callback, now that the browser is back on this site
+#[get("/finish?<next>")]
+pub async fn finish(
+    next: Option<String>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    let next = next.as_deref().and_then(safe_return_to);
+    let user = match pending_login(cookies) {
+        Some(user_id) => User::get(user_id, &conn).await,
+        None => return Flash::error(login_redirect(next), "Your login timed out, try again"),
+    };
+    match user {
+        Ok(user) if !user.disabled => {
+            complete_login(&user, next, &client, cookies, config, &conn).await
+        }
+        Ok(_) => Flash::error(login_redirect(next), "That user is disabled"),
+        Err(_) => Flash::error(login_redirect(next), "An internal server error occurred"),
+    }
+}
+
+fn continue_page(to: &str) -> Template {
+    Template::render("pages/login_continue", json!({ "to": to }))
+}
+
+/* --------------------------------- helpers -------------------------------- */
+
+/// Redeems the code from the callback and checks who it says logged in
+async fn verify(
+    code: &str,
+    request: &LoginRequest,
+    base_url: &BaseUrl,
+    config: &LinkrConfig,
+) -> OidcResult<Identity> {
+    let issuer = config
+        .oidc_issuer
+        .as_deref()
+        .ok_or("Single sign-on isn't set up")?;
+    let provider = oidc::discover(issuer).await?;
+    let id_token = oidc::exchange_code(
+        &provider,
+        config,
+        &redirect_uri(base_url),
+        code,
+        &request.verifier,
+    )
+    .await?;
+    let claims = oidc::verify_id_token(&provider, config, &id_token, &request.nonce).await?;
+    oidc::identity(&claims, config)
+}
+
+/// The account someone from the identity provider logs in to. Only accounts connected to them
+/// are used, since the username they give isn't theirs alone, and new ones are made if that's
+/// turned on. When groups are mapped to permissions, they're updated on every login.
+async fn find_user(identity: &Identity, config: &LinkrConfig, conn: &DbConn) -> OidcResult<User> {
+    const INTERNAL: &str = "An internal server error occurred";
+
+    let mut user = match User::get_by_oidc_subject(identity.subject.clone(), conn).await {
+        Ok(user) => user,
+        Err(Error::NotFound) if config.oidc_create_users => {
+            let permissions = oidc::permissions(identity, config).unwrap_or((false, false));
+            let new_user = InsertableUser::external(
+                identity.username.clone(),
+                permissions,
+                Some(identity.subject.clone()),
+            );
+            return match User::insert(new_user, conn).await {
+                Ok(user) => Ok(user),
+                Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(
+                    "That username is taken. If it's yours, log in to it and connect it from your account page",
+                ),
+                Err(_) => Err(INTERNAL),
+            };
+        }
+        Err(Error::NotFound) => {
+            return Err("Your account isn't connected yet. Log in to it and connect it from your account page, or ask an admin")
+        }
+        Err(_) => return Err(INTERNAL),
+    };
+
+    if let Some(permissions) = oidc::permissions(identity, config) {
+        user.sync_permissions(permissions, conn)
+            .await
+            .map_err(|_| INTERNAL)?;
+    }
+    Ok(user)
+}
@@ -15,51 +15,310 @@
 // You should have received a copy of the GNU General Public License
 // along with Linkr. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::auth;
+use crate::backends::Backend;
+use crate::client::ClientInfo;
+use crate::config::LinkrConfig;
 use crate::db::DbConn;
-use crate::models::links::Link;
+use crate::models::aliases::Alias;
+use crate::models::clicks::Click;
+use crate::models::links::{Link, LinkStatus};
+use crate::models::login_throttles::LoginThrottle;
+use crate::models::sessions::Session;
+use crate::models::settings::Settings;
+use crate::models::tokens::ApiToken;
+use crate::models::two_factor::TwoFactor;
 use crate::models::users::User;
+use crate::routes::link_table::{link_table, TableParams};
+use crate::routes::links::check_can_edit;
+use crate::routes::qr::inline_svg;
+use crate::routes::two_factor::start_setup;
+use crate::totp;
 
+use chrono::Utc;
 use diesel::result::Error;
-use rocket::http::Status;
+use diesel::QueryResult;
+use rocket::http::uri::Origin;
+use rocket::http::{Cookie, CookieJar, Status};
 use rocket::request::FlashMessage;
+use rocket::response::status::Custom;
 use rocket::response::Redirect;
 use rocket::serde::json::serde_json::json;
 use rocket::serde::json::Value;
+use rocket::State;
 use rocket_dyn_templates::Template;
 
+#[derive(Responder)]
+#[allow(clippy::large_enum_variant)]
+pub enum LinkResponse {
+    Redirect(Redirect),
+    Page(Custom<Template>),
+}
+
+/// A request to follow a short, with everything that decides where it goes
+struct Visit<'a> {
+    short: String,
+    /// The path after the short, for the links that match everything under it
+    rest: Option<String>,
+    origin: &'a Origin<'a>,
+    client: ClientInfo,
+    cookies: &'a CookieJar<'a>,
+    flash: Option<FlashMessage<'a>>,
+}
+
 #[get("/<short>", rank = 3)]
-pub async fn link(conn: DbConn, short: String) -> Result<Redirect, Status> {
-    match Link::get(short.to_string(), &conn).await {
-        Ok(link) => Ok(Redirect::permanent(link.long)),
-        Err(Error::NotFound) => Err(Status::NotFound),
-        Err(_) => Err(Status::InternalServerError),
+pub async fn link(
+    conn: DbConn,
+    short: String,
+    origin: &Origin<'_>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    flash: Option<FlashMessage<'_>>,
+    config: &State<LinkrConfig>,
+) -> Result<LinkResponse, Status> {
+    // shorts are alphanumeric, so a trailing + can only ask for the preview
+    let preview = short.ends_with('+');
+    let visit = Visit {
+        short: short.trim_end_matches('+').to_string(),
+        rest: None,
+        origin,
+        client,
+        cookies,
+        flash,
+    };
+    if preview {
+        return preview_link(conn, visit, config).await;
     }
+    follow_link(conn, visit, config).await
+}
+
+/// Shows where a link goes without following it, the same as `/<short>+`.
+/// Prefix and templated links are followed instead, with `preview` as the rest of their path.
+#[get("/<short>/preview", rank = 3)]
+pub async fn preview(
+    conn: DbConn,
+    short: String,
+    origin: &Origin<'_>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    flash: Option<FlashMessage<'_>>,
+    config: &State<LinkrConfig>,
+) -> Result<LinkResponse, Status> {
+    let takes_path = match Link::resolve(short.clone(), &conn).await {
+        Ok(link) => link.prefix || link.is_template(),
+        Err(Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let visit = Visit {
+        short,
+        rest: takes_path.then(|| String::from("preview")),
+        origin,
+        client,
+        cookies,
+        flash,
+    };
+    if takes_path {
+        return follow_link(conn, visit, config).await;
+    }
+    preview_link(conn, visit, config).await
+}
+
+/// Prefix and templated links also match everything under their short
+#[get("/<short>/<_..>", rank = 4)]
+pub async fn prefix_link(
+    conn: DbConn,
+    short: String,
+    origin: &Origin<'_>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    flash: Option<FlashMessage<'_>>,
+    config: &State<LinkrConfig>,
+) -> Result<LinkResponse, Status> {
+    // taken raw so the path is passed on with the same encoding it came in with
+    let rest: Vec<&str> = origin
+        .path()
+        .raw_segments()
+        .skip(1)
+        .map(|segment| segment.as_str())
+        .collect();
+    let visit = Visit {
+        short,
+        rest: Some(rest.join("/")),
+        origin,
+        client,
+        cookies,
+        flash,
+    };
+    follow_link(conn, visit, config).await
 }
 
-#[get("/")]
+async fn follow_link(
+    conn: DbConn,
+    visit: Visit<'_>,
+    config: &LinkrConfig,
+) -> Result<LinkResponse, Status> {
+    let link = match Link::resolve(visit.short.clone(), &conn).await {
+        Ok(link) => link,
+        Err(Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    if visit.rest.is_some() && !link.prefix && !link.is_template() {
+        return Err(Status::NotFound);
+    }
+    if let Some(response) = check_access(&link, &visit, config, &conn).await {
+        return Ok(response);
+    }
+
+    // links outside of their schedule go to their fallback or explain why they don't work
+    let status = link.status(Utc::now());
+    if status != LinkStatus::Active {
+        if let Some(fallback) = link.fallback_url.clone() {
+            return Ok(leave_for_fallback(&link, fallback, config));
+        }
+        return Ok(LinkResponse::Page(unavailable_page(&link, status)));
+    }
+
+    // limited links take their click before redirecting, and stop once they're used up
+    if link.max_clicks.is_some() {
+        match Link::use_click(link.short.clone(), &conn).await {
+            Ok(true) => {}
+            Ok(false) => {
+                if let Some(fallback) = link.fallback_url.clone() {
+                    return Ok(leave_for_fallback(&link, fallback, config));
+                }
+                return Ok(LinkResponse::Page(unavailable_page(
+                    &link,
+                    LinkStatus::Exhausted,
+                )));
+            }
+            Err(_) => return Err(Status::InternalServerError),
+        }
+    }
+
+    // record the click in the background so the redirect isn't held up
+    let query = visit.origin.query().map(|query| query.as_str());
+    let destination = link.destination(visit.rest.as_deref().unwrap_or(""), query);
+    let response = if needs_warning(&link, &destination, config) {
+        LinkResponse::Page(leaving_page(&link, &destination, config))
+    } else {
+        LinkResponse::Redirect(redirect_with_code(&link, destination))
+    };
+    let via = Some(visit.short).filter(|short| *short != link.short);
+    let click = Click::new(link.short, via, visit.client, &config.ip_hash_salt);
+    rocket::tokio::spawn(async move {
+        if let Err(err) = Click::record(click, &conn).await {
+            warn!("Failed to record click: {}", err);
+        }
+    });
+    Ok(response)
+}
+
+/// Internal and password protected links stop the visitor until they've logged in or unlocked
+/// them, and the response that asks them to is returned
+async fn check_access(
+    link: &Link,
+    visit: &Visit<'_>,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> Option<LinkResponse> {
+    // internal links send anyone that isn't logged in to log in first.
+    // the session cookie is strict, so it's left off clicks from other sites. the login page
+    // is reached from a page on this site instead, which sends anyone logged in straight back
+    if link.internal
+        && !auth::from_cookies(visit.cookies, config, conn)
+            .await
+            .is_success()
+    {
+        let next = visit.origin.to_string();
+        let context = json!({ "to": auth::login_path(Some(&next)) });
+        return Some(LinkResponse::Page(Custom(
+            Status::Unauthorized,
+            Template::render("pages/login_continue", context),
+        )));
+    }
+
+    // password protected links ask for it until this browser has unlocked them
+    if let Some(token) = link.unlock_token() {
+        let unlocked = visit
+            .cookies
+            .get_private(&unlock_cookie_name(link))
+            .is_some_and(|cookie| cookie.value() == token);
+        if !unlocked {
+            return Some(LinkResponse::Page(unlock_page(visit)));
+        }
+    }
+
+    None
+}
+
+/// Shows a link's destination and who made it, without counting a click
+async fn preview_link(
+    conn: DbConn,
+    visit: Visit<'_>,
+    config: &LinkrConfig,
+) -> Result<LinkResponse, Status> {
+    let link = match Link::resolve(visit.short.clone(), &conn).await {
+        Ok(link) => link,
+        Err(Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    if let Some(response) = check_access(&link, &visit, config, &conn).await {
+        return Ok(response);
+    }
+
+    let creator = match User::get(link.created_by, &conn).await {
+        Ok(user) => Some(user.username),
+        Err(Error::NotFound) => None,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let status = link.status(Utc::now());
+    let context = json!({
+        "short": visit.short,
+        "long": link.long,
+        "notes": link.notes,
+        "creator": creator,
+        "created_at": link.created_at.format("%F").to_string(),
+        "active": status == LinkStatus::Active,
+        "status": status.as_str(),
+        "takes_path": link.prefix || link.is_template(),
+    });
+    Ok(LinkResponse::Page(Custom(
+        Status::Ok,
+        Template::render("pages/link_preview", &context),
+    )))
+}
+
+/// The private cookie that remembers a browser unlocked a link
+pub fn unlock_cookie_name(link: &Link) -> String {
+    format!("unlocked_{}", link.short)
+}
+
+#[get("/?<params..>")]
 pub async fn index(
     user: User,
+    params: TableParams,
     flash: Option<FlashMessage<'_>>,
     conn: DbConn,
 ) -> Result<Template, Status> {
     // links for table
-    let links = match Link::all_for_user(user.id, &conn).await {
-        Ok(links) => links,
+    let table = match link_table(Some(user.id), params, &conn).await {
+        Ok(table) => table,
         Err(_) => return Err(Status::InternalServerError),
     };
 
     // render template
     let context = json!({
-        "links": links,
+        "table": table,
         "user": user,
         "flash": flash_json(&flash),
     });
     Ok(Template::render("pages/index", &context))
 }
 
-#[get("/manage_links")]
+#[get("/manage_links?<params..>")]
 pub async fn manage_links(
     user: User,
+    params: TableParams,
     flash: Option<FlashMessage<'_>>,
     conn: DbConn,
 ) -> Result<Template, Status> {
@@ -69,24 +328,84 @@ pub async fn manage_links(
     }
 
     // links for table
-    let links = match Link::all(&conn).await {
-        Ok(links) => links,
+    let table = match link_table(None, params, &conn).await {
+        Ok(table) => table,
         Err(_) => return Err(Status::InternalServerError),
     };
 
     // render template
     let context = json!({
-        "links": links,
+        "table": table,
         "user": user,
         "flash": flash_json(&flash)
     });
     Ok(Template::render("pages/manage_links", &context))
 }
 
+#[get("/details/<short>")]
+pub async fn link_details(
+    user: User,
+    short: String,
+    flash: Option<FlashMessage<'_>>,
+    conn: DbConn,
+) -> Result<Template, Status> {
+    check_can_edit(&user, &short, &conn).await?;
+
+    let link = match Link::get(short.clone(), &conn).await {
+        Ok(link) => link,
+        Err(Error::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let total = match Click::count(short.clone(), &conn).await {
+        Ok(total) => total,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let daily = match Click::daily(short, 30, &conn).await {
+        Ok(daily) => daily,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    // scale the bars against the busiest day
+    let busiest = daily.iter().map(|day| day.clicks).max().unwrap_or(0).max(1);
+    let days: Vec<Value> = daily
+        .iter()
+        .map(|day| {
+            json!({
+                "day": day.day,
+                "clicks": day.clicks,
+                "percent": day.clicks * 100 / busiest,
+            })
+        })
+        .collect();
+
+    let by_via = match Click::by_via(link.short.clone(), &conn).await {
+        Ok(by_via) => by_via,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let aliases = match Alias::all_for_link(link.short.clone(), &conn).await {
+        Ok(aliases) => aliases,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let context = json!({
+        "link": link,
+        "aliases": aliases,
+        "protected": link.is_protected(),
+        "total": total,
+        "by_via": by_via,
+        "days": days,
+        "user": user,
+        "flash": flash_json(&flash)
+    });
+    Ok(Template::render("pages/link_details", &context))
+}
+
 #[get("/manage_users")]
 pub async fn manage_users(
     user: User,
     flash: Option<FlashMessage<'_>>,
+    config: &State<LinkrConfig>,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Result<Template, Status> {
     // check permission
@@ -100,10 +419,24 @@ pub async fn manage_users(
         Err(_) => return Err(Status::InternalServerError),
     };
 
+    let lockouts = match LoginThrottle::all_locked(&conn).await {
+        Ok(lockouts) => lockouts,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let settings = match Settings::get(&conn).await {
+        Ok(settings) => settings,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
     // render template
     let context = json!({
         "users": users,
         "user": user,
+        "lockouts": lockouts,
+        "require_two_factor": settings.require_two_factor,
+        "rename_users": backend.stores_passwords(),
+        "sso_name": config.oidc_issuer.as_ref().map(|_| &config.oidc_name),
         "flash": flash_json(&flash)
     });
     Ok(Template::render("pages/manage_users", &context))
@@ -113,9 +446,54 @@ pub async fn manage_users(
 pub async fn manage_account(
     user: User,
     flash: Option<FlashMessage<'_>>,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    backend: &State<Backend>,
+    conn: DbConn,
 ) -> Result<Template, Status> {
+    let tokens = match ApiToken::all_for_user(user.id, &conn).await {
+        Ok(tokens) => tokens,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    // a token that was just created is only ever shown once
+    let new_token = cookies.get_private("new_token").map(|cookie| {
+        cookies.remove_private(Cookie::named("new_token"));
+        cookie.value().to_string()
+    });
+
+    let two_factor = match two_factor_json(&user, cookies, &conn).await {
+        Ok(two_factor) => two_factor,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let sessions = match Session::all_for_user(user.id, &conn).await {
+        Ok(sessions) => sessions,
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let current = match auth::current_session(cookies, config, &conn).await {
+        Ok(current) => current.map(|session| session.id),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let sessions: Vec<Value> = sessions
+        .into_iter()
+        .map(|session| {
+            let mut row = json!(session);
+            row["current"] = json!(Some(session.id) == current);
+            row
+        })
+        .collect();
+
     let context = json!({
         "user": user,
+        "tokens": tokens,
+        "sessions": sessions,
+        "passwords": !config.disable_passwords,
+        "change_password": !config.disable_passwords && backend.stores_passwords(),
+        "change_username": backend.stores_passwords(),
+        "sso_name": config.oidc_issuer.as_ref().map(|_| &config.oidc_name),
+        "new_token": new_token,
+        "two_factor": two_factor,
         "flash": flash_json(&flash)
     });
     Ok(Template::render("pages/manage_account", &context))
@@ -135,20 +513,60 @@ pub async fn new_user(user: User, flash: Option<FlashMessage<'_>>) -> Result<Tem
     Ok(Template::render("pages/new_user", &context))
 }
 
-#[get("/login")]
+#[get("/login?<next>")]
 pub async fn login(
     user: Option<User>,
+    next: Option<String>,
     flash: Option<FlashMessage<'_>>,
+    config: &State<LinkrConfig>,
 ) -> Result<Template, Redirect> {
+    let next = next.as_deref().and_then(auth::safe_return_to);
     match user {
-        Some(_) => Err(Redirect::to("/")),
+        Some(_) => Err(Redirect::to(next.unwrap_or("/").to_string())),
         None => {
-            let context = just_flash_context(&flash);
+            let context = json!({
+                "next": next,
+                "passwords": !config.disable_passwords,
+                "sso_name": config.oidc_issuer.as_ref().map(|_| &config.oidc_name),
+                "flash": flash_json(&flash),
+            });
             Ok(Template::render("pages/login", &context))
         }
     }
 }
 
+/// Where someone enters their code after their password, or sets up two factor if it's
+/// required of them
+#[get("/login/two_factor?<next>")]
+pub async fn login_two_factor(
+    next: Option<String>,
+    cookies: &CookieJar<'_>,
+    flash: Option<FlashMessage<'_>>,
+    conn: DbConn,
+) -> Result<Template, Redirect> {
+    let next = next.as_deref().and_then(auth::safe_return_to);
+    let user = match auth::pending_login(cookies) {
+        Some(user_id) => User::get(user_id, &conn).await,
+        None => return Err(auth::login_redirect(next)),
+    };
+    let user = match user {
+        Ok(user) => user,
+        Err(_) => return Err(auth::login_redirect(next)),
+    };
+
+    let setup = match auth::second_step(&user, &conn).await {
+        Ok(auth::SecondStep::Setup) => Some(setup_json(&user, cookies)),
+        Ok(_) => None,
+        Err(_) => return Err(auth::login_redirect(next)),
+    };
+    let context = json!({
+        "next": next,
+        "setup": setup,
+        "flash": flash_json(&flash),
+    });
+    Ok(Template::render("pages/login_two_factor", &context))
+}
+
 #[get("/setup")]
 pub async fn setup(flash: Option<FlashMessage<'_>>, conn: DbConn) -> Result<Template, Status> {
     match User::count(&conn).await {
@@ -166,6 +584,155 @@ pub async fn setup(flash: Option<FlashMessage<'_>>, conn: DbConn) -> Result<Temp
 
 /* --------------------------------- helpers -------------------------------- */
 
+/// Redirects with the link's status code, but never permanently while the link has a
+/// schedule or a password since browsers would keep using the cached redirect
+fn redirect_with_code(link: &Link, url: String) -> Redirect {
+    match (link.redirect_code, link.is_cacheable()) {
+        (301, true) => Redirect::moved(url),
+        (301, false) | (302, _) => Redirect::found(url),
+        (308, true) => Redirect::permanent(url),
+        _ => Redirect::temporary(url),
+    }
+}
+
+fn leave_for_fallback(link: &Link, fallback: String, config: &LinkrConfig) -> LinkResponse {
+    if needs_warning(link, &fallback, config) {
+        return LinkResponse::Page(leaving_page(link, &fallback, config));
+    }
+    LinkResponse::Redirect(Redirect::temporary(fallback))
+}
+
+/// Whether a visitor should be warned before going to a url, which is only done when
+/// trusted domains have been set up
+fn needs_warning(link: &Link, url: &str, config: &LinkrConfig) -> bool {
+    !link.skip_warning
+        && !config.trusted_domains.is_empty()
+        && !is_trusted(url, &config.trusted_domains)
+}
+
+/// Checks if a url's host is one of the domains, or a subdomain of one
+fn is_trusted(url: &str, trusted_domains: &[String]) -> bool {
+    // browsers end the host at a backslash too, so it can't be used to hide the real one
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority
+        .split(['/', '\\', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    let host_port = authority.rsplit('@').next().unwrap_or_default();
+    let host = match host_port.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host_port.split(':').next().unwrap_or_default(),
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+    trusted_domains.iter().any(|domain| {
+        let domain = domain.trim().trim_start_matches("*.").to_ascii_lowercase();
+        !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+    })
+}
+
+/// Tells the visitor they're leaving for another site before sending them on
+fn leaving_page(link: &Link, url: &str, config: &LinkrConfig) -> Custom<Template> {
+    let context = json!({
+        "short": link.short,
+        "url": url,
+        "delay": config.leaving_delay,
+    });
+    Custom(Status::Ok, Template::render("pages/link_leaving", &context))
+}
+
+fn unlock_page(visit: &Visit<'_>) -> Custom<Template> {
+    let context = json!({
+        "short": visit.short,
+        "next": visit.origin.to_string(),
+        "flash": flash_json(&visit.flash),
+    });
+    Custom(
+        Status::Unauthorized,
+        Template::render("pages/link_unlock", &context),
+    )
+}
+
+fn unavailable_page(link: &Link, status: LinkStatus) -> Custom<Template> {
+    let (code, message, details) = match status {
+        LinkStatus::Scheduled => (
+            Status::NotFound,
+            "That link is not active yet",
+            link.active_from
+                .map(|from| format!("It will be available from {} UTC", from.format("%F %R")))
+                .unwrap_or_default(),
+        ),
+        LinkStatus::Exhausted => (
+            Status::Gone,
+            "That link has been used up",
+            link.max_clicks
+                .map(|max| match max {
+                    1 => "It could only be followed once".to_string(),
+                    _ => format!("It could only be followed {} times", max),
+                })
+                .unwrap_or_default(),
+        ),
+        _ => (
+            Status::Gone,
+            "That link has expired",
+            link.expires_at
+                .map(|until| format!("It stopped working on {} UTC", until.format("%F %R")))
+                .unwrap_or_default(),
+        ),
+    };
+
+    let context = json!({
+        "short": link.short,
+        "code": code.code,
+        "message": message,
+        "details": details,
+    });
+    Custom(code, Template::render("pages/link_unavailable", &context))
+}
+
+async fn two_factor_json(
+    user: &User,
+    cookies: &CookieJar<'_>,
+    conn: &DbConn,
+) -> QueryResult<Value> {
+    let required = Settings::get(conn).await?.requires_two_factor(user);
+
+    // recovery codes that were just made are only ever shown once
+    let new_recovery_codes: Option<Vec<String>> =
+        cookies.get_private("new_recovery_codes").map(|cookie| {
+            cookies.remove_private(Cookie::named("new_recovery_codes"));
+            cookie.value().split(' ').map(String::from).collect()
+        });
+
+    if TwoFactor::get(user.id, conn).await?.is_some() {
+        return Ok(json!({
+            "enabled": true,
+            "required": required,
+            "recovery_codes_left": TwoFactor::recovery_codes_left(user.id, conn).await?,
+            "new_recovery_codes": new_recovery_codes,
+        }));
+    }
+
+    let setup = cookies
+        .get_private("totp_setup")
+        .map(|_| setup_json(user, cookies));
+    Ok(json!({
+        "enabled": false,
+        "required": required,
+        "setup": setup,
+    }))
+}
+
+/// The secret being set up, as a QR code to scan and as text to type in
+fn setup_json(user: &User, cookies: &CookieJar<'_>) -> Value {
+    let secret = start_setup(cookies);
+    let uri = totp::provisioning_uri(&secret, &user.username);
+    json!({
+        "secret": secret,
+        "qr": inline_svg(&uri, 200),
+    })
+}
+
 fn flash_json(flash: &Option<FlashMessage<'_>>) -> Value {
     match flash {
         Some(flash) => json!({
@@ -0,0 +1,213 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use diesel::result::Error;
+use qrcode::{types::Color, EcLevel, QrCode};
+use rocket::http::{ContentType, Status};
+
+use crate::client::BaseUrl;
+use crate::db::DbConn;
+use crate::models::links::Link;
+use crate::models::users::User;
+use crate::routes::links::check_can_edit;
+
+#[derive(FromFormField, Clone, Copy)]
+pub enum QrFormat {
+    #[field(value = "svg")]
+    Svg,
+    #[field(value = "png")]
+    Png,
+}
+
+/// How much of the code can be damaged or covered and still scan
+#[derive(FromFormField, Clone, Copy)]
+pub enum QrErrorCorrection {
+    #[field(value = "L")]
+    Low,
+    #[field(value = "M")]
+    Medium,
+    #[field(value = "Q")]
+    Quartile,
+    #[field(value = "H")]
+    High,
+}
+
+impl QrErrorCorrection {
+    fn level(self) -> EcLevel {
+        match self {
+            QrErrorCorrection::Low => EcLevel::L,
+            QrErrorCorrection::Medium => EcLevel::M,
+            QrErrorCorrection::Quartile => EcLevel::Q,
+            QrErrorCorrection::High => EcLevel::H,
+        }
+    }
+}
+
+#[derive(FromForm)]
+pub struct QrParams {
+    format: Option<QrFormat>,
+    /// The smallest width the image can be, in pixels
+    size: Option<u32>,
+    ec: Option<QrErrorCorrection>,
+    /// The blank border around the code, in modules
+    margin: Option<u32>,
+}
+
+const DEFAULT_SIZE: u32 = 256;
+const MIN_SIZE: u32 = 32;
+const MAX_SIZE: u32 = 2048;
+
+/// Scanners expect a border of at least 4 modules
+const DEFAULT_MARGIN: u32 = 4;
+const MAX_MARGIN: u32 = 16;
+
+/// Renders a QR code of a link's full short url as an SVG or PNG
+#[get("/<short>/qr?<params..>")]
+pub async fn qr(
+    short: String,
+    params: QrParams,
+    base_url: BaseUrl,
+    user: User,
+    conn: DbConn,
+) -> Result<(ContentType, Vec<u8>), (Status, &'static str)> {
+    check_can_edit(&user, &short, &conn)
+        .await
+        .map_err(|status| (status, ""))?;
+    let link = match Link::get(short, &conn).await {
+        Ok(link) => link,
+        Err(Error::NotFound) => return Err((Status::NotFound, "")),
+        Err(_) => return Err((Status::InternalServerError, "")),
+    };
+
+    let size = params.size.unwrap_or(DEFAULT_SIZE);
+    if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+        return Err((
+            Status::UnprocessableEntity,
+            "The size has to be between 32 and 2048 pixels",
+        ));
+    }
+    let margin = params.margin.unwrap_or(DEFAULT_MARGIN);
+    if margin > MAX_MARGIN {
+        return Err((
+            Status::UnprocessableEntity,
+            "The margin can be at most 16 modules",
+        ));
+    }
+
+    let url = format!("{}/{}", base_url.0, link.short);
+    let ec = params.ec.unwrap_or(QrErrorCorrection::Medium);
+    let code = match QrCode::with_error_correction_level(url, ec.level()) {
+        Ok(code) => code,
+        Err(_) => {
+            return Err((
+                Status::UnprocessableEntity,
+                "That link is too long for a QR code",
+            ))
+        }
+    };
+    let modules = Modules::new(&code, margin as usize);
+
+    match params.format.unwrap_or(QrFormat::Svg) {
+        QrFormat::Svg => {
+            let svg = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+                render_svg(&modules, size)
+            );
+            Ok((ContentType::SVG, svg.into_bytes()))
+        }
+        QrFormat::Png => match render_png(&modules, size) {
+            Ok(png) => Ok((ContentType::PNG, png)),
+            Err(_) => Err((Status::InternalServerError, "")),
+        },
+    }
+}
+
+/// A code's modules with the margin around them
+struct Modules {
+    dark: Vec<bool>,
+    width: usize,
+}
+
+impl Modules {
+    fn new(code: &QrCode, margin: usize) -> Modules {
+        let width = code.width() + margin * 2;
+        let mut dark = vec![false; width * width];
+        for y in 0..code.width() {
+            for x in 0..code.width() {
+                dark[(y + margin) * width + x + margin] = code[(x, y)] == Color::Dark;
+            }
+        }
+        Modules { dark, width }
+    }
+
+    /// How many pixels wide each module is to reach at least `size` pixels
+    fn scale(&self, size: u32) -> usize {
+        (size as usize).div_ceil(self.width)
+    }
+}
+
+/// A QR code of any text as an SVG to put straight into a page
+pub fn inline_svg(data: &str, size: u32) -> Option<String> {
+    let code = QrCode::new(data).ok()?;
+    Some(render_svg(
+        &Modules::new(&code, DEFAULT_MARGIN as usize),
+        size,
+    ))
+}
+
+fn render_svg(modules: &Modules, size: u32) -> String {
+    let pixels = modules.width * modules.scale(size);
+    let mut path = String::new();
+    for (i, _) in modules.dark.iter().enumerate().filter(|(_, dark)| **dark) {
+        path.push_str(&format!(
+            "M{} {}h1v1h-1z",
+            i % modules.width,
+            i / modules.width
+        ));
+    }
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{size}" height="{size}" "#,
+            r#"viewBox="0 0 {width} {width}" shape-rendering="crispEdges">"#,
+            r##"<rect width="{width}" height="{width}" fill="#fff"/>"##,
+            r##"<path fill="#000" d="{path}"/></svg>"##
+        ),
+        size = pixels,
+        width = modules.width,
+        path = path,
+    )
+}
+
+fn render_png(modules: &Modules, size: u32) -> Result<Vec<u8>, png::EncodingError> {
+    let scale = modules.scale(size);
+    let pixels = modules.width * scale;
+    let mut data = Vec::with_capacity(pixels * pixels);
+    for y in 0..pixels {
+        for x in 0..pixels {
+            let dark = modules.dark[(y / scale) * modules.width + x / scale];
+            data.push(if dark { 0 } else { 255 });
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, pixels as u32, pixels as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(png)
+}
@@ -0,0 +1,86 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::Utc;
+use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar};
+use rocket::response::{Flash, Redirect};
+
+use crate::crypto::{generate_token, hash_token};
+use crate::db::DbConn;
+use crate::models::tokens::{ApiToken, NewApiToken, TokenScope};
+use crate::models::users::User;
+
+#[derive(FromForm)]
+pub struct NewToken {
+    name: String,
+    scope: TokenScope,
+}
+
+#[post("/new", data = "<token_form>")]
+pub async fn new(
+    token_form: Form<NewToken>,
+    user: User,
+    cookies: &CookieJar<'_>,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    let new_token = token_form.into_inner();
+    if new_token.name.is_empty() {
+        return Flash::error(Redirect::to("/manage_account"), "Your token needs a name");
+    }
+
+    let token = generate_token();
+    let api_token = NewApiToken {
+        user_id: user.id,
+        name: new_token.name,
+        token_hash: hash_token(&token),
+        scope: new_token.scope.as_str().to_string(),
+        created_at: Utc::now(),
+    };
+
+    match ApiToken::insert(api_token, &conn).await {
+        Ok(_) => {
+            // the token itself is never stored, so it's shown once on the account page
+            cookies.add_private(Cookie::new("new_token", token));
+            Flash::success(Redirect::to("/manage_account"), "Token created")
+        }
+        Err(_) => Flash::error(
+            Redirect::to("/manage_account"),
+            "An internal server error occurred",
+        ),
+    }
+}
+
+#[derive(FromForm)]
+pub struct TokenID {
+    id: i32,
+}
+
+#[post("/revoke", data = "<id_form>")]
+pub async fn revoke(id_form: Form<TokenID>, user: User, conn: DbConn) -> Flash<Redirect> {
+    match ApiToken::revoke(id_form.into_inner().id, user.id, &conn).await {
+        Ok(0) => Flash::error(
+            Redirect::to("/manage_account"),
+            "That token cannot be found",
+        ),
+        Ok(_) => Flash::success(Redirect::to("/manage_account"), "Token revoked"),
+        Err(_) => Flash::error(
+            Redirect::to("/manage_account"),
+            "An internal server error occurred",
+        ),
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::Utc;
+use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use rocket_sync_db_pools::diesel::QueryResult;
+
+use crate::auth::{
+    log_in, login_redirect, pending_login, release_login_attempt, reserve_login_attempt,
+    safe_return_to, second_step, second_step_redirect, SecondStep,
+};
+use crate::client::ClientInfo;
+use crate::config::LinkrConfig;
+use crate::crypto::{generate_recovery_code, hash_recovery_code};
+use crate::db::DbConn;
+use crate::models::settings::Settings;
+use crate::models::two_factor::TwoFactor;
+use crate::models::users::User;
+use crate::totp;
+
+/// How many recovery codes are handed out at a time
+const RECOVERY_CODES: usize = 10;
+
+/* ---------------------------------- login --------------------------------- */
+
+#[derive(FromForm)]
+pub struct LoginCode {
+    code: String,
+    next: Option<String>,
+}
+
+/// The second login step, after the password was checked in `users::login`
+#[post("/login", data = "<code_form>")]
+pub async fn login(
+    code_form: Form<LoginCode>,
+    client: ClientInfo,
+    cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    let form = code_form.into_inner();
+    let next = form.next.as_deref();
+    let user_id = match pending_login(cookies) {
+        Some(user_id) => user_id,
+        None => return Flash::error(login_redirect(next), "Your login timed out, try again"),
+    };
+    let user = match User::get(user_id, &conn).await {
+        Ok(user) if !user.disabled => user,
+        Ok(_) => return Flash::error(login_redirect(next), "That user is disabled"),
+        Err(_) => return Flash::error(login_redirect(next), "An internal server error occurred"),
+    };
+
+    // codes are guessed the same way passwords are, so they're throttled the same way too
+    match reserve_login_attempt(&user.username, &client, config, &conn).await {
+        Ok(None) => {}
+        Ok(Some(msg)) => return Flash::error(second_step_redirect(next), msg),
+        Err(_) => return Flash::error(login_redirect(next), "An internal server error occurred"),
+    }
+
+    let (to, msg) = match second_step(&user, &conn).await {
+        Ok(SecondStep::None) => (next.and_then(safe_return_to).unwrap_or("/"), "Logged in"),
+        Ok(SecondStep::Code(two_factor)) => {
+            match check_code(&two_factor, &form.code, &conn).await {
+                Ok(true) => (next.and_then(safe_return_to).unwrap_or("/"), "Logged in"),
+                Ok(false) => return Flash::error(second_step_redirect(next), "Incorrect code"),
+                Err(_) => {
+                    return Flash::error(
+                        second_step_redirect(next),
+                        "An internal server error occurred",
+                    )
+                }
+            }
+        }
+        // required accounts set it up here, then see their recovery codes on their account page
+        Ok(SecondStep::Setup) => match enroll(user.id, &form.code, cookies, &conn).await {
+            Ok(()) => (
+                "/manage_account",
+                "Two-factor authentication set up. Save your recovery codes",
+            ),
+            Err(msg) => {
+                // a code that doesn't set up two factor isn't a guess at logging in
+                return match release_login_attempt(&user.username, &client, config, &conn).await {
+                    Ok(()) => Flash::error(second_step_redirect(next), msg),
+                    Err(_) => {
+                        Flash::error(login_redirect(next), "An internal server error occurred")
+                    }
+                };
+            }
+        },
+        Err(_) => return Flash::error(login_redirect(next), "An internal server error occurred"),
+    };
+
+    if release_login_attempt(&user.username, &client, config, &conn)
+        .await
+        .is_err()
+    {
+        return Flash::error(login_redirect(next), "An internal server error occurred");
+    }
+
+    match log_in(&user, &client, cookies, config, &conn).await {
+        Ok(()) => Flash::success(Redirect::to(to.to_string()), msg),
+        Err(_) => Flash::error(login_redirect(next), "An internal server error occurred"),
+    }
+}
+
+/* ---------------------------------- setup --------------------------------- */
+
+/// Starts setting up two factor with a new secret, which isn't saved until a code from it is entered
+#[post("/start")]
+pub async fn start(_user: User, cookies: &CookieJar<'_>) -> Redirect {
+    start_setup(cookies);
+    Redirect::to("/manage_account")
+}
+
+#[post("/cancel")]
+pub async fn cancel(_user: User, cookies: &CookieJar<'_>) -> Redirect {
+    cookies.remove_private(Cookie::named("totp_setup"));
+    Redirect::to("/manage_account")
+}
+
+#[derive(FromForm)]
+pub struct Code {
+    code: String,
+}
+
+#[post("/confirm", data = "<code_form>")]
+pub async fn confirm(
+    code_form: Form<Code>,
+    user: User,
+    cookies: &CookieJar<'_>,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    let code = code_form.into_inner().code;
+    match enroll(user.id, &code, cookies, &conn).await {
+        Ok(()) => Flash::success(
+            Redirect::to("/manage_account"),
+            "Two-factor authentication turned on. Save your recovery codes",
+        ),
+        Err(msg) => Flash::error(Redirect::to("/manage_account"), msg),
+    }
+}
+
+/// The secret being set up, making a new one if there isn't one yet
+pub fn start_setup(cookies: &CookieJar<'_>) -> String {
+    if let Some(cookie) = cookies.get_private("totp_setup") {
+        return cookie.value().to_string();
+    }
+    let secret = totp::generate_secret();
+    cookies.add_private(Cookie::new("totp_setup", secret.clone()));
+    secret
+}
+
+/// Saves the secret being set up once a code from it shows the authenticator app has it
+async fn enroll(
+    user_id: i32,
+    code: &str,
+    cookies: &CookieJar<'_>,
+    conn: &DbConn,
+) -> Result<(), &'static str> {
+    let secret = match cookies.get_private("totp_setup") {
+        Some(cookie) => cookie.value().to_string(),
+        None => return Err("Start setting up two-factor authentication again"),
+    };
+    let step = match totp::verify(&secret, code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Err("Incorrect code"),
+    };
+
+    let codes = new_recovery_codes();
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    let two_factor = TwoFactor::new(user_id, secret, step);
+    if TwoFactor::enroll(two_factor, hashes, conn).await.is_err() {
+        return Err("An internal server error occurred");
+    }
+
+    cookies.remove_private(Cookie::named("totp_setup"));
+    show_recovery_codes(cookies, &codes);
+    Ok(())
+}
+
+/* ------------------------------- managing it ------------------------------ */
+
+#[post("/recovery_codes", data = "<code_form>")]
+pub async fn regenerate_recovery_codes(
+    code_form: Form<Code>,
+    user: User,
+    cookies: &CookieJar<'_>,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    let code = code_form.into_inner().code;
+    if let Err(msg) = check_current_code(&user, &code, &conn).await {
+        return Flash::error(Redirect::to("/manage_account"), msg);
+    }
+
+    let codes = new_recovery_codes();
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    match TwoFactor::set_recovery_codes(user.id, hashes, &conn).await {
+        Ok(()) => {
+            show_recovery_codes(cookies, &codes);
+            Flash::success(
+                Redirect::to("/manage_account"),
+                "New recovery codes made. The old ones no longer work",
+            )
+        }
+        Err(_) => Flash::error(
+            Redirect::to("/manage_account"),
+            "An internal server error occurred",
+        ),
+    }
+}
+
+#[post("/disable", data = "<code_form>")]
+pub async fn disable(code_form: Form<Code>, user: User, conn: DbConn) -> Flash<Redirect> {
+    match Settings::get(&conn).await {
+        Ok(settings) if settings.requires_two_factor(&user) => {
+            return Flash::error(
+                Redirect::to("/manage_account"),
+                "Two-factor authentication is required for your account",
+            )
+        }
+        Ok(_) => {}
+        Err(_) => {
+            return Flash::error(
+                Redirect::to("/manage_account"),
+                "An internal server error occurred",
+            )
+        }
+    }
+
+    let code = code_form.into_inner().code;
+    if let Err(msg) = check_current_code(&user, &code, &conn).await {
+        return Flash::error(Redirect::to("/manage_account"), msg);
+    }
+
+    match TwoFactor::remove(user.id, &conn).await {
+        Ok(_) => Flash::success(
+            Redirect::to("/manage_account"),
+            "Two-factor authentication turned off",
+        ),
+        Err(_) => Flash::error(
+            Redirect::to("/manage_account"),
+            "An internal server error occurred",
+        ),
+    }
+}
+
+#[derive(FromForm)]
+pub struct Requirement {
+    require: bool,
+}
+
+/// Makes everyone that can manage links or users set up two factor
+#[post("/require", data = "<require_form>")]
+pub async fn require(
+    require_form: Form<Requirement>,
+    user: User,
+    conn: DbConn,
+) -> Result<Flash<Redirect>, Status> {
+    if !user.manage_users {
+        return Err(Status::Forbidden);
+    }
+    let require = require_form.into_inner().require;
+
+    // otherwise turning it on would log them out until they set it up
+    if require {
+        match TwoFactor::get(user.id, &conn).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Ok(Flash::error(
+                    Redirect::to("/manage_users"),
+                    "Set up two-factor authentication on your own account first",
+                ))
+            }
+            Err(_) => return Err(Status::InternalServerError),
+        }
+    }
+
+    match Settings::update_require_two_factor(require, &conn).await {
+        Ok(_) if require => Ok(Flash::success(
+            Redirect::to("/manage_users"),
+            "Two-factor authentication is now required",
+        )),
+        Ok(_) => Ok(Flash::success(
+            Redirect::to("/manage_users"),
+            "Two-factor authentication is no longer required",
+        )),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+/* --------------------------------- helpers -------------------------------- */
+
+/// Accepts either a code from the authenticator app or one of the user's recovery codes,
+/// using it up so it can't be entered again
+async fn check_code(two_factor: &TwoFactor, code: &str, conn: &DbConn) -> QueryResult<bool> {
+    if let Some(step) = totp::verify(&two_factor.secret, code, Utc::now().timestamp()) {
+        return TwoFactor::use_step(two_factor.user_id, step, conn).await;
+    }
+    TwoFactor::use_recovery_code(two_factor.user_id, hash_recovery_code(code), conn).await
+}
+
+async fn check_current_code(user: &User, code: &str, conn: &DbConn) -> Result<(), &'static str> {
+    let two_factor = match TwoFactor::get(user.id, conn).await {
+        Ok(Some(two_factor)) => two_factor,
+        Ok(None) => return Err("Two-factor authentication is not turned on"),
+        Err(_) => return Err("An internal server error occurred"),
+    };
+    match check_code(&two_factor, code, conn).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("Incorrect code"),
+        Err(_) => Err("An internal server error occurred"),
+    }
+}
+
+fn new_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect()
+}
+
+/// Recovery codes are only stored hashed, so they're shown once on the account page
+fn show_recovery_codes(cookies: &CookieJar<'_>, codes: &[String]) {
+    cookies.add_private(Cookie::new("new_recovery_codes", codes.join(" ")));
+}
@@ -18,12 +18,22 @@
 use rocket::form::Form;
 use rocket::http::{Cookie, CookieJar, Status};
 use rocket::response::{Flash, Redirect};
+use rocket::State;
 use rocket_sync_db_pools::diesel::result::DatabaseErrorKind;
 use rocket_sync_db_pools::diesel::result::Error;
 use rocket_sync_db_pools::diesel::QueryResult;
 
+use crate::auth::{
+    log_in, log_out, login_redirect, release_login_attempt, reserve_login_attempt, safe_return_to,
+    second_step, second_step_redirect, start_second_step, SecondStep,
+};
+use crate::backends::{confirm_password, AuthError, Backend};
+use crate::client::ClientInfo;
+use crate::config::LinkrConfig;
 use crate::crypto::encrypt_pw;
 use crate::db::DbConn;
+use crate::models::login_throttles::LoginThrottle;
+use crate::models::sessions::Session;
 use crate::models::users::{InsertableUser, User};
 
 /* ----------------------------------- new ---------------------------------- */
@@ -40,7 +50,9 @@ pub struct NewUser {
 pub async fn new(
     new_user_form: Form<NewUser>,
     user: Option<User>,
+    client: ClientInfo,
     cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
     conn: DbConn,
 ) -> Result<Flash<Redirect>, Status> {
     // if user is authorized and has manage user permission, allow creation of not original
@@ -91,8 +103,15 @@ pub async fn new(
     // insert user
     match User::insert(new_user, &conn).await {
         Ok(new_user) => {
-            if orig {
-                cookies.add_private(Cookie::new("user_id", new_user.id.to_string()));
+            if orig
+                && log_in(&new_user, &client, cookies, config, &conn)
+                    .await
+                    .is_err()
+            {
+                return Ok(Flash::error(
+                    Redirect::to("/login"),
+                    "Account created, but it could not be logged in",
+                ));
             }
             Ok(Flash::success(
                 Redirect::to(success_to),
@@ -116,37 +135,142 @@ pub async fn new(
 pub struct Login {
     username: String,
     password: String,
+    /// The page to go back to once logged in
+    next: Option<String>,
 }
 
 #[post("/login", data = "<user_form>")]
 pub async fn login(
     user_form: Form<Login>,
+    client: ClientInfo,
     cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Flash<Redirect> {
     let login = user_form.into_inner();
-    match User::get_by_name(login.username, &conn).await {
-        Ok(selected_user) => {
-            if selected_user.disabled {
-                return Flash::error(Redirect::to("/login"), "That user is disabled");
-            }
-            if selected_user.verify(&login.password) {
-                cookies.add_private(Cookie::new("user_id", selected_user.id.to_string()));
-                return Flash::success(Redirect::to("/"), "Logged in");
-            } else {
-                return Flash::error(Redirect::to("/login"), "Invalid username/password");
+    let next = login.next.as_deref().and_then(safe_return_to);
+    if config.disable_passwords {
+        return Flash::error(
+            login_redirect(next),
+            "Logging in with a password is turned off",
+        );
+    }
+
+    // locked out logins aren't checked at all, so guessing can't keep the server busy hashing
+    match reserve_login_attempt(&login.username, &client, config, &conn).await {
+        Ok(None) => {}
+        Ok(Some(msg)) => return Flash::error(login_redirect(next), msg),
+        Err(_) => return Flash::error(login_redirect(next), "An internal server error occurred"),
+    }
+
+    let result = backend
+        .authenticate(&login.username, &login.password, &conn)
+        .await;
+    // the attempt stays counted as a failure only when the password was wrong
+    if !matches!(result, Err(AuthError::Invalid))
+        && release_login_attempt(&login.username, &client, config, &conn)
+            .await
+            .is_err()
+    {
+        return Flash::error(login_redirect(next), "An internal server error occurred");
+    }
+    let selected_user = match result {
+        Ok(selected_user) => selected_user,
+        Err(AuthError::Invalid) => {
+            return Flash::error(login_redirect(next), "Invalid username/password")
+        }
+        Err(AuthError::Failed(msg)) => return Flash::error(login_redirect(next), msg),
+    };
+    if selected_user.disabled {
+        return Flash::error(login_redirect(next), "That user is disabled");
+    }
+    complete_login(&selected_user, next, &client, cookies, config, &conn).await
+}
+
+/// Logs in someone who was just verified, unless their account needs a code too, which
+/// they're sent to enter first
+pub async fn complete_login(
+    user: &User,
+    next: Option<&str>,
+    client: &ClientInfo,
+    cookies: &CookieJar<'_>,
+    config: &LinkrConfig,
+    conn: &DbConn,
+) -> Flash<Redirect> {
+    match second_step(user, conn).await {
+        Ok(SecondStep::None) => match log_in(user, client, cookies, config, conn).await {
+            Ok(()) => {
+                let next = next.unwrap_or("/").to_string();
+                Flash::success(Redirect::to(next), "Logged in")
             }
+            Err(_) => Flash::error(login_redirect(next), "An internal server error occurred"),
+        },
+        Ok(SecondStep::Code(_)) => {
+            start_second_step(cookies, user.id);
+            Flash::success(
+                second_step_redirect(next),
+                "Enter the code from your authenticator app",
+            )
         }
-        Err(_) => Flash::error(Redirect::to("/login"), "Invalid username/password"),
+        Ok(SecondStep::Setup) => {
+            start_second_step(cookies, user.id);
+            Flash::success(
+                second_step_redirect(next),
+                "Your account needs two-factor authentication set up",
+            )
+        }
+        Err(_) => Flash::error(login_redirect(next), "An internal server error occurred"),
     }
 }
 
 #[get("/logout")]
-pub async fn logout(cookies: &CookieJar<'_>) -> Redirect {
-    cookies.remove_private(Cookie::named("user_id"));
+pub async fn logout(cookies: &CookieJar<'_>, conn: DbConn) -> Redirect {
+    log_out(cookies, &conn).await;
     Redirect::to("/login")
 }
 
+/* -------------------------------- sessions -------------------------------- */
+
+#[derive(FromForm)]
+pub struct SessionID {
+    id: i32,
+}
+
+/// Logs out one of the user's own sessions, like a browser they no longer use
+#[post("/sessions/revoke", data = "<id_form>")]
+pub async fn revoke_session(id_form: Form<SessionID>, user: User, conn: DbConn) -> Flash<Redirect> {
+    match Session::revoke(id_form.into_inner().id, user.id, &conn).await {
+        Ok(0) => Flash::error(
+            Redirect::to("/manage_account"),
+            "That session cannot be found",
+        ),
+        Ok(_) => Flash::success(Redirect::to("/manage_account"), "Session logged out"),
+        Err(_) => Flash::error(
+            Redirect::to("/manage_account"),
+            "An internal server error occurred",
+        ),
+    }
+}
+
+#[post("/sessions/revoke_all")]
+pub async fn revoke_all_sessions(
+    user: User,
+    cookies: &CookieJar<'_>,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    match Session::revoke_all(user.id, &conn).await {
+        Ok(_) => {
+            cookies.remove_private(Cookie::named("session"));
+            Flash::success(Redirect::to("/login"), "Logged out everywhere")
+        }
+        Err(_) => Flash::error(
+            Redirect::to("/manage_account"),
+            "An internal server error occurred",
+        ),
+    }
+}
+
 /* -------------------------------- destruct -------------------------------- */
 
 #[derive(FromForm)]
@@ -181,8 +305,33 @@ pub async fn enable_by_id(id_form: Form<ID>, user: User, conn: DbConn) -> Flash<
     match_destruct_result_other(User::enable(action_id, &conn).await, "enable")
 }
 
+/// Lets a username or ip that failed to log in too many times try again right away
+#[post("/lockouts/clear", data = "<id_form>")]
+pub async fn clear_lockout(
+    id_form: Form<ID>,
+    user: User,
+    conn: DbConn,
+) -> Result<Flash<Redirect>, Status> {
+    if !user.manage_users {
+        return Err(Status::Forbidden);
+    }
+    match LoginThrottle::clear_by_id(id_form.into_inner().id, &conn).await {
+        Ok(0) => Ok(Flash::error(
+            Redirect::to("/manage_users"),
+            "That lockout cannot be found",
+        )),
+        Ok(_) => Ok(Flash::success(
+            Redirect::to("/manage_users"),
+            "Lockout cleared",
+        )),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
 #[derive(FromForm)]
 pub struct Password {
+    /// left out when passwords are turned off
+    #[field(default = "")]
     password: String,
 }
 
@@ -191,10 +340,12 @@ pub async fn delete_current(
     pw_form: Form<Password>,
     user: User,
     cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Flash<Redirect> {
     let pw = pw_form.into_inner().password;
-    if let Err(flash) = check_destruct_current(&pw, &user, "delete") {
+    if let Err(flash) = check_destruct_current(&pw, &user, config, backend, &conn, "delete").await {
         return flash;
     }
     match_result_current(User::delete(user.id, &conn).await, cookies, "delete")
@@ -205,10 +356,13 @@ pub async fn disable_current(
     pw_form: Form<Password>,
     user: User,
     cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Flash<Redirect> {
     let pw = pw_form.into_inner().password;
-    if let Err(flash) = check_destruct_current(&pw, &user, "disable") {
+    if let Err(flash) = check_destruct_current(&pw, &user, config, backend, &conn, "disable").await
+    {
         return flash;
     }
     match_result_current(User::disable(user.id, &conn).await, cookies, "disable")
@@ -234,7 +388,7 @@ fn match_result_current(
 ) -> Flash<Redirect> {
     match result {
         Ok(_) => {
-            cookies.remove_private(Cookie::named("user_id"));
+            cookies.remove_private(Cookie::named("session"));
             Flash::success(Redirect::to("/login"), format!("User {}d", verb))
         }
         Err(Error::NotFound) => Flash::error(Redirect::to("/"), "That user does not exist."),
@@ -289,9 +443,12 @@ pub async fn check_destruct_other(
     return Ok(());
 }
 
-pub fn check_destruct_current(
+pub async fn check_destruct_current(
     pw: &str,
     user: &User,
+    config: &LinkrConfig,
+    backend: &Backend,
+    conn: &DbConn,
     verb: &'static str,
 ) -> Result<(), Flash<Redirect>> {
     // block if user to delete is original
@@ -302,6 +459,11 @@ pub fn check_destruct_current(
         ));
     }
 
+    // nobody knows their password when they're turned off, so the confirmation is enough
+    if config.disable_passwords {
+        return Ok(());
+    }
+
     // check password
     if pw == "" {
         return Err(Flash::error(
@@ -309,11 +471,17 @@ pub fn check_destruct_current(
             "Enter your current password to delete your account",
         ));
     }
-    if !user.verify(pw) {
-        return Err(Flash::error(
-            Redirect::to("/manage_account"),
-            "Incorrect password",
-        ));
+    match confirm_password(backend, user, pw, conn).await {
+        Ok(()) => {}
+        Err(AuthError::Invalid) => {
+            return Err(Flash::error(
+                Redirect::to("/manage_account"),
+                "Incorrect password",
+            ))
+        }
+        Err(AuthError::Failed(msg)) => {
+            return Err(Flash::error(Redirect::to("/manage_account"), msg))
+        }
     }
     return Ok(());
 }
@@ -363,10 +531,16 @@ pub struct UsernameUpdate {
 pub async fn update_username(
     username_form: Form<UsernameUpdate>,
     user: User,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Status {
     let username_update = username_form.into_inner();
 
+    // directory accounts are found by username, so renaming one would hand it to someone else
+    if !backend.stores_passwords() {
+        return Status::Forbidden;
+    }
+
     // anyone can change their own username
     // users with manage_users can update all usernames
     if username_update.user_id != user.id && !user.manage_users {
@@ -390,10 +564,18 @@ pub struct NewUsername {
 pub async fn update_own_username(
     username_form: Form<NewUsername>,
     user: User,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Result<Flash<Redirect>, Status> {
     let new_name = username_form.into_inner().username;
 
+    if !backend.stores_passwords() {
+        return Ok(Flash::error(
+            Redirect::to("/manage_account"),
+            "Your username can only be changed in your directory",
+        ));
+    }
+
     match User::update_username(user.id, new_name, &conn).await {
         Ok(_) => Ok(Flash::success(
             Redirect::to("/manage_account"),
@@ -411,6 +593,44 @@ pub async fn update_own_username(
     }
 }
 
+#[derive(FromForm)]
+pub struct OidcSubjectUpdate {
+    id: i32,
+    subject: String,
+}
+
+/// Connects someone else's account to the identity provider, or disconnects it when the
+/// subject is left blank
+#[post("/update/oidc_subject", data = "<subject_form>")]
+pub async fn update_oidc_subject(
+    subject_form: Form<OidcSubjectUpdate>,
+    user: User,
+    conn: DbConn,
+) -> Flash<Redirect> {
+    let update = subject_form.into_inner();
+    if let Err(flash) = check_destruct_other(update.id, &user, &conn, "connect").await {
+        return flash;
+    }
+
+    let subject = Some(update.subject.trim().to_string()).filter(|subject| !subject.is_empty());
+    let connected = subject.is_some();
+    match User::set_oidc_subject(update.id, subject, &conn).await {
+        Ok(_) if connected => Flash::success(Redirect::to("/manage_users"), "User connected"),
+        Ok(_) => Flash::success(Redirect::to("/manage_users"), "User disconnected"),
+        Err(Error::NotFound) => {
+            Flash::error(Redirect::to("/manage_users"), "That user cannot be found.")
+        }
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Flash::error(
+            Redirect::to("/manage_users"),
+            "That subject is connected to another account",
+        ),
+        Err(_) => Flash::error(
+            Redirect::to("/manage_users"),
+            "An internal server error occurred.",
+        ),
+    }
+}
+
 #[derive(FromForm)]
 pub struct PasswordUpdate {
     current_pw: String,
@@ -422,10 +642,25 @@ pub async fn update_password(
     pw_form: Form<PasswordUpdate>,
     user: User,
     cookies: &CookieJar<'_>,
+    config: &State<LinkrConfig>,
+    backend: &State<Backend>,
     conn: DbConn,
 ) -> Result<Flash<Redirect>, Status> {
     let passwords = pw_form.into_inner();
 
+    if config.disable_passwords {
+        return Ok(Flash::error(
+            Redirect::to("/manage_account"),
+            "Passwords are turned off",
+        ));
+    }
+    if !backend.stores_passwords() {
+        return Ok(Flash::error(
+            Redirect::to("/manage_account"),
+            "Your password can only be changed in your directory",
+        ));
+    }
+
     if !user.verify(&passwords.current_pw) {
         return Ok(Flash::error(
             Redirect::to("/manage_account"),
@@ -444,7 +679,7 @@ pub async fn update_password(
 
     match User::update_password(user.id, pw_hash, &conn).await {
         Ok(_) => {
-            cookies.remove_private(Cookie::named("user_id"));
+            cookies.remove_private(Cookie::named("session"));
             Ok(Flash::success(Redirect::to("/login"), "Password changed!"))
         }
         Err(_) => Ok(Flash::error(
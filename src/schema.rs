@@ -1,3 +1,42 @@
+table! {
+    aliases (alias) {
+        alias -> Text,
+        short -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        scope -> Text,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    clicks (id) {
+        id -> Int8,
+        short -> Text,
+        clicked_at -> Timestamptz,
+        referrer -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        ip_hash -> Nullable<Text>,
+        via -> Nullable<Text>,
+    }
+}
+
+table! {
+    link_tags (short, tag_id) {
+        short -> Text,
+        tag_id -> Int4,
+    }
+}
+
 table! {
     links (short) {
         short -> Text,
@@ -5,6 +44,70 @@ table! {
         notes -> Text,
         created_at -> Timestamptz,
         created_by -> Int4,
+        active_from -> Nullable<Timestamptz>,
+        expires_at -> Nullable<Timestamptz>,
+        fallback_url -> Nullable<Text>,
+        redirect_code -> Int2,
+        prefix -> Bool,
+        pw_hash -> Nullable<Text>,
+        internal -> Bool,
+        max_clicks -> Nullable<Int4>,
+        clicks_left -> Nullable<Int4>,
+        skip_warning -> Bool,
+    }
+}
+
+table! {
+    login_throttles (id) {
+        id -> Int4,
+        kind -> Text,
+        subject -> Text,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+    }
+}
+
+table! {
+    settings (id) {
+        id -> Int4,
+        require_two_factor -> Bool,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+table! {
+    two_factor (user_id) {
+        user_id -> Int4,
+        secret -> Text,
+        last_step -> Int8,
+        created_at -> Timestamptz,
     }
 }
 
@@ -17,9 +120,31 @@ table! {
         manage_links -> Bool,
         manage_users -> Bool,
         disabled -> Bool,
+        oidc_subject -> Nullable<Text>,
     }
 }
 
+joinable!(aliases -> links (short));
+joinable!(api_tokens -> users (user_id));
+joinable!(clicks -> links (short));
+joinable!(link_tags -> links (short));
+joinable!(link_tags -> tags (tag_id));
 joinable!(links -> users (created_by));
+joinable!(recovery_codes -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(two_factor -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(links, users,);
+allow_tables_to_appear_in_same_query!(
+    aliases,
+    api_tokens,
+    clicks,
+    link_tags,
+    links,
+    login_throttles,
+    recovery_codes,
+    sessions,
+    settings,
+    tags,
+    two_factor,
+    users,
+);
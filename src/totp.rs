@@ -0,0 +1,83 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand_core::{OsRng, RngCore};
+use sha1::Sha1;
+
+/// How long each code lasts, in seconds
+const STEP: i64 = 30;
+
+const DIGITS: u32 = 6;
+
+/// Codes from the step before and after are accepted too, for clocks that are a little off
+const SKEW: i64 = 1;
+
+const ISSUER: &str = "Linkr";
+
+/// A new random secret, base32 encoded the way authenticator apps take it
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// The `otpauth://` uri authenticator apps scan to add a secret
+pub fn provisioning_uri(secret: &str, username: &str) -> String {
+    let label = format!("{}:{}", ISSUER, username);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        utf8_percent_encode(&label, NON_ALPHANUMERIC),
+        secret,
+        ISSUER,
+        DIGITS,
+        STEP
+    )
+}
+
+/// Checks a code against the secret at a unix time, giving back the time step it was for.
+/// The step has to be remembered so the same code can't be used again.
+pub fn verify(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let now = unix_time / STEP;
+    (now - SKEW..=now + SKEW).find(|step| code_at(&key, *step) == Some(code))
+}
+
+/// The code for a time step, from RFC 6238
+fn code_at(key: &[u8], step: i64) -> Option<u32> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).ok()?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation from RFC 4226
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Some(binary % 10u32.pow(DIGITS))
+}
@@ -0,0 +1,127 @@
+// Copyright (C) 2020 Liam Rosenfeld
+//
+// This file is part of Linkr (https://github.com/liamrosenfeld/linkr).
+//
+// Linkr is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Linkr is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Linkr. If not, see <http://www.gnu.org/licenses/>.
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything but unreserved characters is encoded in a filled in path segment
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Queries keep the characters that give them their structure and existing escapes
+const QUERY: &AsciiSet = &SEGMENT.remove(b'&').remove(b'=').remove(b'+').remove(b'%');
+
+/// What can be filled in to a long from the request that's redirecting:
+/// `{1}` to `{9}` are the path segments after the short, `{path}` is all of them,
+/// and `{query}` is the query string
+enum Placeholder {
+    Segment(usize),
+    Path,
+    Query,
+}
+
+impl Placeholder {
+    fn parse(name: &str) -> Option<Placeholder> {
+        match name {
+            "path" => Some(Placeholder::Path),
+            "query" => Some(Placeholder::Query),
+            _ => match name.parse() {
+                Ok(index @ 1..=9) => Some(Placeholder::Segment(index)),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Splits a long into literal text and placeholders
+fn parts(long: &str) -> Result<Vec<Result<&str, Placeholder>>, &'static str> {
+    let mut parts = vec![];
+    let mut rest = long;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or("That long has a { without a closing }")?;
+        let placeholder = Placeholder::parse(&rest[start + 1..start + end]).ok_or(
+            "That long has an unknown placeholder, only {1} to {9}, {path} and {query} can be used",
+        )?;
+        parts.push(Ok(&rest[..start]));
+        parts.push(Err(placeholder));
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err("That long has a } without an opening {");
+    }
+    parts.push(Ok(rest));
+    Ok(parts)
+}
+
+pub fn has_placeholders(long: &str) -> bool {
+    long.contains('{')
+}
+
+/// Checks that every placeholder is known and that none of them can change where
+/// the long points to, so it can't be turned into a redirect to any site
+pub fn validate(long: &str) -> Result<(), &'static str> {
+    parts(long)?;
+
+    let after_scheme = long.find("://").map_or(0, |index| index + 3);
+    let host_end = long[after_scheme..]
+        .find('/')
+        .map_or(long.len(), |index| after_scheme + index);
+    if long[..host_end].contains('{') {
+        return Err("Placeholders can only be used after the domain of a long");
+    }
+
+    Ok(())
+}
+
+/// Fills in the placeholders of a long from the rest of the path and the query,
+/// which are expected to still be percent encoded
+pub fn fill(long: &str, rest: &str, query: Option<&str>) -> String {
+    let parts = match parts(long) {
+        Ok(parts) => parts,
+        Err(_) => return long.to_string(),
+    };
+    let segments: Vec<String> = rest
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let decoded = percent_decode_str(segment).decode_utf8_lossy();
+            utf8_percent_encode(&decoded, SEGMENT).to_string()
+        })
+        .collect();
+
+    let mut url = String::new();
+    for part in parts {
+        match part {
+            Ok(text) => url.push_str(text),
+            Err(Placeholder::Segment(index)) => {
+                if let Some(segment) = segments.get(index - 1) {
+                    url.push_str(segment);
+                }
+            }
+            Err(Placeholder::Path) => url.push_str(&segments.join("/")),
+            Err(Placeholder::Query) => {
+                let query = query.unwrap_or("");
+                url.extend(utf8_percent_encode(query, QUERY));
+            }
+        }
+    }
+    url
+}
@@ -0,0 +1,3 @@
+/* -------------- move on right away, from a page on this site -------------- */
+
+location.replace(document.getElementById("continue-link").href);
@@ -0,0 +1,15 @@
+/* ----------------- count down, then follow the leaving link ---------------- */
+
+const leavingLink = document.getElementById("leaving-link");
+let secondsLeft = Number(leavingLink.dataset.delay);
+
+if (secondsLeft > 0) {
+    const countdown = setInterval(() => {
+        secondsLeft -= 1;
+        document.getElementById("leaving-seconds").textContent = secondsLeft;
+        if (secondsLeft <= 0) {
+            clearInterval(countdown);
+            location.href = leavingLink.href;
+        }
+    }, 1000);
+}
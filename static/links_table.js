@@ -71,6 +71,114 @@ function updateButtonClicked(short) {
     }
 }
 
+/**
+ * @param {string} short
+ */
+function tagsButtonClicked(short) {
+    let button = document.getElementById(`${short}-tags-update`);
+    let tagsElement = document.getElementById(`${short}-tags`);
+
+    if (button.textContent === "Edit") {
+        let input = document.createElement("input");
+        input.value = tagsElement.dataset.tags;
+        input.placeholder = "comma separated";
+        tagsElement.replaceWith(input);
+        input.id = `${short}-tags`;
+        button.textContent = "Save";
+
+    } else if (button.textContent === "Save") {
+        sendTagsUpdate(short, tagsElement.value).then(success => {
+            if (success) {
+                // reload so the tag filter includes any new tags
+                location.reload();
+            }
+        });
+    } else {
+        console.error("button is not named correctly");
+    }
+}
+
+/**
+ * @param {string} short
+ */
+function scheduleButtonClicked(short) {
+    let button = document.getElementById(`${short}-schedule-update`);
+    let form = document.getElementById(`${short}-schedule-form`);
+    let schedule = document.getElementById(`${short}-schedule`);
+
+    if (button.textContent === "Edit") {
+        form.hidden = false;
+        schedule.hidden = true;
+        button.textContent = "Save";
+    } else if (button.textContent === "Save") {
+        sendScheduleUpdate(short, form).then(success => {
+            if (success) {
+                // reload so the status reflects the new schedule
+                location.reload();
+            }
+        });
+    } else {
+        console.error("button is not named correctly");
+    }
+}
+
+/**
+ * @param {string} short
+ */
+function updateRedirectCode(short) {
+    const code = document.getElementById(`${short}-redirect`).value;
+
+    const data = { short: short, redirect_code: code };
+    const dataEncoded = new URLSearchParams(data).toString();
+    const options = {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/x-www-form-urlencoded",
+        },
+        body: dataEncoded,
+    };
+    fetch("/api/links/update/redirect", options).then((response) => {
+        switch (response.status) {
+            case 200:
+                document.getElementById("manage-output").textContent =
+                    "Redirect type updated!";
+                break;
+            case 404:
+                document.getElementById("manage-output").textContent =
+                    "That link does not exist. Please refresh your page. (Code: 404)";
+                break;
+            case 500:
+                document.getElementById("manage-output").textContent =
+                    "There was an internal server error. (Code: 500)";
+                break;
+            default:
+                document.getElementById("manage-output").textContent =
+                    `An error has occurred. (Code: ${response.status}).`;
+                break;
+        }
+    });
+}
+
+/**
+ * @param {string} short
+ */
+function qrButtonClicked(short) {
+    let button = document.getElementById(`${short}-qr-toggle`);
+    let qrElement = document.getElementById(`${short}-qr`);
+
+    if (qrElement.hidden) {
+        // only loaded once it's asked for so the table doesn't fetch every code
+        let image = qrElement.querySelector("img");
+        if (!image.src) {
+            image.src = `/api/links/${short}/qr?format=svg&size=160`;
+        }
+        qrElement.hidden = false;
+        button.textContent = "Hide QR";
+    } else {
+        qrElement.hidden = true;
+        button.textContent = "QR";
+    }
+}
 
 /* ------------------------- functions for functions ------------------------ */
 
@@ -99,6 +207,10 @@ async function sendUpdate(short, newLong) {
             document.getElementById("manage-output").textContent =
                 "That link does not exist. Please refresh your page. (Code: 404)";
             return false;
+        case 422:
+            document.getElementById("manage-output").textContent =
+                await response.text();
+            return false;
         case 500:
             document.getElementById("manage-output").textContent =
                 "There was an internal server error. (Code: 500)";
@@ -109,3 +221,71 @@ async function sendUpdate(short, newLong) {
             return false;
     }
 }
+
+/**
+ * @param {string} short
+ * @param {HTMLFormElement} form
+ */
+async function sendScheduleUpdate(short, form) {
+    const data = new URLSearchParams(new FormData(form));
+    data.append("short", short);
+    const options = {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/x-www-form-urlencoded",
+        },
+        body: data.toString(),
+    };
+
+    let response = await fetch("/api/links/update/schedule", options);
+    switch (response.status) {
+        case 200:
+            return true;
+        case 404:
+            document.getElementById("manage-output").textContent =
+                "That link does not exist. Please refresh your page. (Code: 404)";
+            return false;
+        case 422:
+            document.getElementById("manage-output").textContent =
+                await response.text();
+            return false;
+        default:
+            document.getElementById("manage-output").textContent =
+                `An error has occurred. (Code: ${response.status}).`;
+            return false;
+    }
+}
+
+/**
+ * @param {string} short
+ * @param {string} tags
+ */
+async function sendTagsUpdate(short, tags) {
+    const data = { short: short, tags: tags };
+    const dataEncoded = new URLSearchParams(data).toString();
+    const options = {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/x-www-form-urlencoded",
+        },
+        body: dataEncoded,
+    };
+
+    let response = await fetch("/api/links/update/tags", options);
+    switch (response.status) {
+        case 200:
+            return true;
+        case 404:
+            document.getElementById("manage-output").textContent =
+                "That link does not exist. Please refresh your page. (Code: 404)";
+            return false;
+        case 422:
+            document.getElementById("manage-output").textContent =
+                await response.text();
+            return false;
+        default:
+            document.getElementById("manage-output").textContent =
+                `An error has occurred. (Code: ${response.status}).`;
+            return false;
+    }
+}